@@ -2,17 +2,21 @@ package com.demohouse.topup.controller;
 
 
 import com.demohouse.topup.grpc.vault.PinCodeResponse;
+import com.demohouse.topup.grpc.vault.Product;
 import com.demohouse.topup.grpc.vault.ReservationResponse;
 import com.demohouse.topup.grpc.vault.StatusResponse;
 import com.demohouse.topup.model.web.response.ApiResponse;
 import com.demohouse.topup.model.web.response.request.GenerationReqDto;
 import com.demohouse.topup.model.web.response.request.TakePinCodeReqDto;
 import com.demohouse.topup.service.PinCodeService;
+import io.grpc.Status;
+import io.grpc.StatusRuntimeException;
 import org.springframework.http.HttpStatus;
 import org.springframework.web.bind.annotation.*;
 import org.springframework.web.multipart.MultipartFile;
 
 import java.io.IOException;
+import java.util.List;
 
 @RestController
 @RequestMapping("/api/v1/pin-code")
@@ -43,7 +47,8 @@ public class PinCodeController {
 
     @PostMapping("/generate")
     public ApiResponse<?> generate(@RequestBody GenerationReqDto req) {
-        StatusResponse response = pinCodeService.generatePinCode(req.getCount());
+        Product product = product(req.getOperator(), req.getDenomination(), req.getCurrency());
+        StatusResponse response = pinCodeService.generatePinCode(req.getCount(), product);
         if (response.getSuccess())
             return ApiResponse.success(response.getMessage());
         else
@@ -54,8 +59,14 @@ public class PinCodeController {
     }
 
     @PostMapping("/reserve")
-    public ApiResponse<?> reserve() {
-        ReservationResponse response = pinCodeService.reservePinCode();
+    public ApiResponse<?> reserve(@RequestParam(value = "operator", required = false) String operator,
+                                  @RequestParam(value = "denomination", required = false) Long denomination,
+                                  @RequestParam(value = "currency", required = false) String currency) {
+        // Without a product filter any available PIN is reserved
+        Product product = operator == null && denomination == null && currency == null
+                ? null
+                : product(operator, denomination, currency);
+        ReservationResponse response = pinCodeService.reservePinCode(product);
         if (response.getSuccess())
             return ApiResponse.success(response.getId());
         else
@@ -67,19 +78,37 @@ public class PinCodeController {
 
     @PostMapping("/take")
     public ApiResponse<?> take(@RequestBody TakePinCodeReqDto dto) {
-        PinCodeResponse response = pinCodeService.takePinCode(dto.getReservationId());
-        if (response.getSuccess())
-            return ApiResponse.success(response.getPinCode());
-        else
-            return ApiResponse.failure(
-                    HttpStatus.INTERNAL_SERVER_ERROR,
-                    response.getMessage()
-            );
+        List<PinCodeResponse> responses;
+        try {
+            responses = pinCodeService.takePinCode(dto.getReservationId());
+        } catch (StatusRuntimeException e) {
+            // The vault refuses reservations that are unknown, lapsed or already closed
+            return ApiResponse.failure(httpStatus(e.getStatus()), e.getStatus().getDescription());
+        }
+        for (PinCodeResponse response : responses) {
+            if (!response.getSuccess())
+                return ApiResponse.failure(
+                        HttpStatus.INTERNAL_SERVER_ERROR,
+                        response.getMessage()
+                );
+        }
+        if (responses.isEmpty())
+            return ApiResponse.failure(HttpStatus.INTERNAL_SERVER_ERROR, "Reservation held no PIN");
+        // A single reservation keeps its plain PIN answer; bulk ones return every PIN
+        if (responses.size() == 1)
+            return ApiResponse.success(responses.get(0).getPinCode());
+        return ApiResponse.success(responses.stream().map(PinCodeResponse::getPinCode).toList());
     }
 
     @PostMapping("/upload")
-    public ApiResponse<String> uploadFile(@RequestParam("file") MultipartFile file) throws IOException {
-        StatusResponse response = pinCodeService.uploadPinCodes(file.getInputStream(), file.getName());
+    public ApiResponse<String> uploadFile(@RequestParam("file") MultipartFile file,
+                                          @RequestParam("operator") String operator,
+                                          @RequestParam("denomination") Long denomination,
+                                          @RequestParam("currency") String currency,
+                                          @RequestParam(value = "supplier", required = false) String supplier) throws IOException {
+        Product product = product(operator, denomination, currency);
+        StatusResponse response = pinCodeService.uploadPinCodes(
+                file.getInputStream(), file.getOriginalFilename(), product, supplier);
         if (response.getSuccess())
             return ApiResponse.success(response.getMessage());
         else
@@ -88,5 +117,28 @@ public class PinCodeController {
                     response.getMessage()
             );
     }
-}
 
+    private static HttpStatus httpStatus(Status status) {
+        return switch (status.getCode()) {
+            case INVALID_ARGUMENT -> HttpStatus.BAD_REQUEST;
+            case NOT_FOUND -> HttpStatus.NOT_FOUND;
+            // Lapsed, released or recalled: the reservation is gone for good
+            case FAILED_PRECONDITION -> HttpStatus.GONE;
+            // Already taken, or its PINs were handed to another reservation
+            case ALREADY_EXISTS, ABORTED -> HttpStatus.CONFLICT;
+            default -> HttpStatus.INTERNAL_SERVER_ERROR;
+        };
+    }
+
+    // Missing fields are left unset for the vault to reject
+    private static Product product(String operator, Long denomination, String currency) {
+        Product.Builder product = Product.newBuilder();
+        if (operator != null)
+            product.setOperator(operator);
+        if (denomination != null)
+            product.setDenomination(denomination);
+        if (currency != null)
+            product.setCurrency(currency);
+        return product.build();
+    }
+}
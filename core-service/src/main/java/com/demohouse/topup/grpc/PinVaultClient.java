@@ -2,7 +2,6 @@ package com.demohouse.topup.grpc;
 
 import com.demohouse.topup.grpc.vault.*;
 import com.google.protobuf.ByteString;
-import com.google.protobuf.Empty;
 import io.grpc.stub.StreamObserver;
 import net.devh.boot.grpc.client.inject.GrpcClient;
 import org.slf4j.Logger;
@@ -10,6 +9,8 @@ import org.slf4j.LoggerFactory;
 import org.springframework.stereotype.Component;
 
 import java.io.InputStream;
+import java.util.ArrayList;
+import java.util.List;
 import java.util.concurrent.CompletableFuture;
 import java.util.concurrent.ExecutionException;
 
@@ -29,23 +30,30 @@ public class PinVaultClient {
         return blockingStub.getPinCode(request);
     }
 
-    public PinCodeResponse takePinCode(String id) {
+    public List<PinCodeResponse> takePinCode(String id) {
         IdRequest request = IdRequest.newBuilder()
                 .setId(id).build();
-        return blockingStub.takePinCode(request);
+        // Every PIN of the reservation is sold by the time the stream starts
+        List<PinCodeResponse> responses = new ArrayList<>();
+        blockingStub.takePinCode(request).forEachRemaining(responses::add);
+        return responses;
     }
 
-    public StatusResponse generatePinCode(int count) {
+    public StatusResponse generatePinCode(int count, Product product) {
         GenerationRequest request = GenerationRequest.newBuilder()
-                .setCount(count).build();
+                .setCount(count)
+                .setProduct(product).build();
         return blockingStub.generatePinCode(request);
     }
 
-    public ReservationResponse reservePinCode() {
-        return blockingStub.reservePinCode(Empty.getDefaultInstance());
+    public ReservationResponse reservePinCode(Product product) {
+        ReservationRequest.Builder request = ReservationRequest.newBuilder();
+        if (product != null)
+            request.setProduct(product);
+        return blockingStub.reservePinCode(request.build());
     }
 
-    public StatusResponse uploadPinCodes(InputStream input, String fileName) {
+    public StatusResponse uploadPinCodes(InputStream input, String fileName, Product product, String supplier) {
         LOGGER.info("Starting upload of file: {}", fileName);
 
         CompletableFuture<StatusResponse> responseFuture = new CompletableFuture<>();
@@ -78,6 +86,8 @@ public class PinVaultClient {
             if ((bytesRead = input.read(buffer)) != -1) {
                 requestObserver.onNext(PinCodeChunk.newBuilder()
                         .setFileName(fileName)
+                        .setProduct(product)
+                        .setSupplier(supplier == null ? "" : supplier)
                         .setContent(ByteString.copyFrom(buffer, 0, bytesRead))
                         .build());
             }
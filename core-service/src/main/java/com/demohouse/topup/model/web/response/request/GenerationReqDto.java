@@ -3,6 +3,9 @@ package com.demohouse.topup.model.web.response.request;
 public class GenerationReqDto {
 
     private Integer count;
+    private String operator;
+    private Long denomination;
+    private String currency;
 
     public Integer getCount() {
         return count;
@@ -11,5 +14,29 @@ public class GenerationReqDto {
     public void setCount(Integer count) {
         this.count = count;
     }
+
+    public String getOperator() {
+        return operator;
+    }
+
+    public void setOperator(String operator) {
+        this.operator = operator;
+    }
+
+    public Long getDenomination() {
+        return denomination;
+    }
+
+    public void setDenomination(Long denomination) {
+        this.denomination = denomination;
+    }
+
+    public String getCurrency() {
+        return currency;
+    }
+
+    public void setCurrency(String currency) {
+        this.currency = currency;
+    }
 }
 
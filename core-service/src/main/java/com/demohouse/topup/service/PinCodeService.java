@@ -1,20 +1,22 @@
 package com.demohouse.topup.service;
 
 import com.demohouse.topup.grpc.vault.PinCodeResponse;
+import com.demohouse.topup.grpc.vault.Product;
 import com.demohouse.topup.grpc.vault.ReservationResponse;
 import com.demohouse.topup.grpc.vault.StatusResponse;
 
 import java.io.InputStream;
+import java.util.List;
 
 public interface PinCodeService {
 
     PinCodeResponse getPinCode(String id);
 
-    PinCodeResponse takePinCode(String reservationId);
+    List<PinCodeResponse> takePinCode(String reservationId);
 
-    StatusResponse generatePinCode(int count);
+    StatusResponse generatePinCode(int count, Product product);
 
-    ReservationResponse reservePinCode();
+    ReservationResponse reservePinCode(Product product);
 
-    StatusResponse uploadPinCodes(InputStream input, String filename);
+    StatusResponse uploadPinCodes(InputStream input, String filename, Product product, String supplier);
 }
\ No newline at end of file
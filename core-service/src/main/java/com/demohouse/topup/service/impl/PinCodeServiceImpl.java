@@ -2,12 +2,14 @@ package com.demohouse.topup.service.impl;
 
 import com.demohouse.topup.grpc.PinVaultClient;
 import com.demohouse.topup.grpc.vault.PinCodeResponse;
+import com.demohouse.topup.grpc.vault.Product;
 import com.demohouse.topup.grpc.vault.ReservationResponse;
 import com.demohouse.topup.grpc.vault.StatusResponse;
 import com.demohouse.topup.service.PinCodeService;
 import org.springframework.stereotype.Service;
 
 import java.io.InputStream;
+import java.util.List;
 
 @Service
 public class PinCodeServiceImpl implements PinCodeService {
@@ -23,22 +25,22 @@ public class PinCodeServiceImpl implements PinCodeService {
     }
 
     @Override
-    public PinCodeResponse takePinCode(String reservationId) {
+    public List<PinCodeResponse> takePinCode(String reservationId) {
         return pinVaultClient.takePinCode(reservationId);
     }
 
     @Override
-    public StatusResponse generatePinCode(int count) {
-        return pinVaultClient.generatePinCode(count);
+    public StatusResponse generatePinCode(int count, Product product) {
+        return pinVaultClient.generatePinCode(count, product);
     }
 
     @Override
-    public ReservationResponse reservePinCode() {
-        return pinVaultClient.reservePinCode();
+    public ReservationResponse reservePinCode(Product product) {
+        return pinVaultClient.reservePinCode(product);
     }
 
     @Override
-    public StatusResponse uploadPinCodes(InputStream input, String filename) {
-        return pinVaultClient.uploadPinCodes(input, filename);
+    public StatusResponse uploadPinCodes(InputStream input, String filename, Product product, String supplier) {
+        return pinVaultClient.uploadPinCodes(input, filename, product, supplier);
     }
 }
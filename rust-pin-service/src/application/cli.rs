@@ -0,0 +1,87 @@
+use std::error::Error;
+use std::io::Write;
+
+use rand::RngCore;
+use tonic::transport::{Certificate, Channel, ClientTlsConfig, Identity};
+
+use crate::cipher::{shamir, SecretBytes, SecretString};
+use crate::vault::vault_unseal_service_client::VaultUnsealServiceClient;
+use crate::vault::UnsealShareRequest;
+
+const DEFAULT_KEY_BYTES: usize = 32;
+const DEFAULT_UNSEAL_ADDR: &str = "https://127.0.0.1:9098";
+
+/// `keygen --shares N --threshold M [--bytes 32]`
+///
+/// Prints N shares of a fresh random master key, then the share digests that go
+/// into `cipher.unseal.share_digests`; the key itself is never shown.
+pub fn keygen(args: &[String]) -> Result<(), Box<dyn Error>> {
+    let shares: u8 = flag(args, "--shares")?.ok_or("--shares is required")?.parse()?;
+    let threshold: u8 = flag(args, "--threshold")?.ok_or("--threshold is required")?.parse()?;
+    let bytes = match flag(args, "--bytes")? {
+        Some(bytes) => bytes.parse()?,
+        None => DEFAULT_KEY_BYTES,
+    };
+
+    let mut key = SecretBytes::new(vec![0u8; bytes]);
+    rand::thread_rng().fill_bytes(key.expose_mut());
+
+    let split = shamir::split(&key, threshold, shares)?;
+    for (i, share) in split.iter().enumerate() {
+        println!("Share {}/{}: {}", i + 1, shares, share.expose());
+    }
+    println!("Any {} of these shares unseal the vault. Give each one to a different operator.", threshold);
+    println!("share_digests:");
+    for share in &split {
+        println!("  - {}", shamir::share_digest(&shamir::decode_share(share.expose())?));
+    }
+    Ok(())
+}
+
+/// `unseal --ca FILE --cert FILE --key FILE [--addr URL] [--domain NAME]`
+///
+/// Reads one share from stdin, so it stays out of shell history, and submits it
+/// to a vault waiting in sealed mode. The certificate and key identify the
+/// operator to the vault; `--ca` is what the vault's certificate must chain to.
+pub async fn unseal(args: &[String]) -> Result<(), Box<dyn Error>> {
+    let addr = flag(args, "--addr")?.unwrap_or(DEFAULT_UNSEAL_ADDR).to_string();
+    let read = |name: &str| -> Result<Vec<u8>, Box<dyn Error>> {
+        let path = flag(args, name)?.ok_or_else(|| format!("{} is required", name))?;
+        std::fs::read(path).map_err(|e| format!("cannot read {}: {}", path, e).into())
+    };
+    let mut tls = ClientTlsConfig::new()
+        .ca_certificate(Certificate::from_pem(read("--ca")?))
+        .identity(Identity::from_pem(read("--cert")?, read("--key")?));
+    if let Some(domain) = flag(args, "--domain")? {
+        tls = tls.domain_name(domain);
+    }
+    let channel = Channel::from_shared(addr)?.tls_config(tls)?.connect().await?;
+
+    print!("Key share: ");
+    std::io::stdout().flush()?;
+    let mut share = SecretString::new(String::with_capacity(1024));
+    std::io::stdin().read_line(share.expose_mut())?;
+
+    let mut client = VaultUnsealServiceClient::new(channel);
+    let status = client
+        .submit_unseal_share(UnsealShareRequest {
+            share: share.expose().trim().to_string(),
+        })
+        .await?
+        .into_inner();
+    println!(
+        "{} ({}/{} shares, sealed: {})",
+        status.message, status.received, status.threshold, status.sealed
+    );
+    Ok(())
+}
+
+fn flag<'a>(args: &'a [String], name: &str) -> Result<Option<&'a str>, String> {
+    match args.iter().position(|arg| arg == name) {
+        Some(i) => args
+            .get(i + 1)
+            .map(|value| Some(value.as_str()))
+            .ok_or_else(|| format!("{} needs a value", name)),
+        None => Ok(None),
+    }
+}
@@ -1,5 +1,7 @@
+use bson::doc;
 use mongodb::{
     Client,
+    ClientSession,
     options::{ClientOptions},
 };
 use std::{time::Duration};
@@ -15,6 +17,8 @@ pub mod utils;
 pub struct DatabaseClient {
     pub conf: DatasourceConf,
     pub client: Option<Client>,
+    // Multi-document transactions need a replica set or a sharded cluster
+    pub transactions: bool,
 }
 
 impl DatabaseClient {
@@ -22,6 +26,7 @@ impl DatabaseClient {
         Self {
             conf: env.datasource.clone(),
             client: None,
+            transactions: false,
         }
     }
 
@@ -42,11 +47,27 @@ impl DatabaseClient {
             Some(Duration::from_secs(self.conf.max_idle_time.unwrap_or(300)));
 
         let client = Client::with_options(client_options)?;
+        let hello = client
+            .database(&self.conf.database_name)
+            .run_command(doc! { "hello": 1 }, None)
+            .await?;
+        self.transactions = hello.contains_key("setName") || hello.get_str("msg") == Ok("isdbgrid");
+        if !self.transactions {
+            println!("Standalone MongoDB: multi-document writes fall back to compensation");
+        }
         self.client = Some(client);
 
         Ok(())
     }
 
+    pub async fn start_session(&self) -> mongodb::error::Result<ClientSession> {
+        self.client
+            .as_ref()
+            .expect("Client not initialized properly!")
+            .start_session(None)
+            .await
+    }
+
     pub fn db(&self) -> mongodb::Database {
         self.client
             .as_ref()
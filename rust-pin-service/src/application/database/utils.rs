@@ -1,3 +1,5 @@
+use mongodb::error::{Error, ErrorKind, WriteFailure};
+
 pub fn build_mongo_uri(
     host: &str,
     port: u16,
@@ -5,13 +7,22 @@ pub fn build_mongo_uri(
     username: Option<&str>,
     password: Option<&str>
 ) -> String {
-    if let (Some(user), Some(pass)) = (username, password) {
-        if !user.is_empty() && !pass.is_empty() {
-            return format!(
-                "mongodb://{}:{}@{}:{}/{}",
-                user, pass, host, port, db
-            );
-        }
+    if let (Some(user), Some(pass)) = (username, password)
+        && !user.is_empty() && !pass.is_empty() {
+        return format!(
+            "mongodb://{}:{}@{}:{}/{}",
+            user, pass, host, port, db
+        );
     }
     format!("mongodb://{}:{}/{}", host, port, db)
-}
\ No newline at end of file
+}
+
+const DUPLICATE_KEY: i32 = 11000;
+
+pub fn is_duplicate_key(error: &Error) -> bool {
+    match error.kind.as_ref() {
+        ErrorKind::Write(WriteFailure::WriteError(e)) => e.code == DUPLICATE_KEY,
+        ErrorKind::Command(e) => e.code == DUPLICATE_KEY,
+        _ => false,
+    }
+}
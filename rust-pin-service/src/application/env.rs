@@ -1,23 +1,215 @@
-use crate::cipher::Algorithm;
+use crate::cipher::{Algorithm, SecretString};
+use crate::cipher::kdf::Kdf;
 use serde::Deserialize;
 use std::fs;
+use std::net::{IpAddr, Ipv4Addr};
 use serde_yaml;
 
 fn def_alg() -> Algorithm {
     Algorithm::Aes256Gcm
 }
 
+fn def_unseal_bind() -> IpAddr {
+    IpAddr::V4(Ipv4Addr::LOCALHOST)
+}
+
+/// Reference to secret material kept outside config.yml. `AppEnv::from` replaces
+/// each reference with the `Loaded` value, which is wiped on drop and redacted in `Debug`.
+#[derive(Clone, Debug, Deserialize)]
+#[serde(try_from = "SecretRef")]
+pub enum SecretSource {
+    File(String),
+    Env(String),
+    Loaded(SecretString),
+}
+
+// What config.yml spells out: exactly one of `file` or `env`
+#[derive(Deserialize)]
+#[serde(deny_unknown_fields)]
+struct SecretRef {
+    file: Option<String>,
+    env: Option<String>,
+}
+
+impl TryFrom<SecretRef> for SecretSource {
+    type Error = String;
+
+    fn try_from(secret: SecretRef) -> Result<Self, Self::Error> {
+        match (secret.file, secret.env) {
+            (Some(path), None) => Ok(SecretSource::File(path)),
+            (None, Some(name)) => Ok(SecretSource::Env(name)),
+            _ => Err("secret must set exactly one of `file` or `env`".into()),
+        }
+    }
+}
+
+impl SecretSource {
+    pub fn load(&self) -> Result<SecretSource, String> {
+        match self {
+            SecretSource::File(path) => {
+                check_secret_file(path)?;
+                let mut value = SecretString::new(
+                    fs::read_to_string(path)
+                        .map_err(|e| format!("cannot read secret file {}: {}", path, e))?,
+                );
+                // Trim in place so no untracked copy of the secret is left behind
+                let len = value.expose().trim_end().len();
+                value.expose_mut().truncate(len);
+                Ok(SecretSource::Loaded(value))
+            }
+            SecretSource::Env(name) => std::env::var(name)
+                .map(|value| SecretSource::Loaded(value.into()))
+                .map_err(|e| format!("cannot read secret from ${}: {}", name, e)),
+            SecretSource::Loaded(_) => Ok(self.clone()),
+        }
+    }
+
+    pub fn expose(&self) -> Option<&str> {
+        match self {
+            SecretSource::Loaded(value) => Some(value.expose()),
+            _ => None,
+        }
+    }
+}
+
+#[cfg(unix)]
+fn check_secret_file(path: &str) -> Result<(), String> {
+    use std::os::unix::fs::PermissionsExt;
+
+    let mode = fs::metadata(path)
+        .map_err(|e| format!("cannot stat secret file {}: {}", path, e))?
+        .permissions()
+        .mode();
+    if mode & 0o077 != 0 {
+        return Err(format!(
+            "secret file {} must not be accessible by group or others (mode {:o})",
+            path,
+            mode & 0o777
+        ));
+    }
+    Ok(())
+}
+
+#[cfg(not(unix))]
+fn check_secret_file(_path: &str) -> Result<(), String> {
+    Ok(())
+}
+
+#[derive(Clone, Debug, Deserialize)]
+pub struct KeyConf {
+    pub id: String,
+    // Raw key bytes as `hex:...` or `base64:...`
+    pub key: Option<SecretSource>,
+    // Alternatively a passphrase, stretched with `kdf` over `salt`
+    pub passphrase: Option<SecretSource>,
+    pub salt: Option<String>,
+    #[serde(default)]
+    pub kdf: Kdf,
+}
+
+impl KeyConf {
+    fn load_secrets(&mut self) -> Result<(), String> {
+        let id = self.id.clone();
+        for source in [&mut self.key, &mut self.passphrase].into_iter().flatten() {
+            *source = source.load().map_err(|e| format!("key {}: {}", id, e))?;
+        }
+        Ok(())
+    }
+}
+
+#[derive(Clone, Debug, Deserialize)]
+pub struct KeyringConf {
+    pub active: KeyConf,
+    #[serde(default)]
+    pub retired: Vec<KeyConf>,
+    // Refuse records sealed without AAD; turn on once re-encryption reports none left
+    #[serde(default)]
+    pub require_aad: bool,
+}
+
+impl KeyringConf {
+    pub fn load_secrets(&mut self) -> Result<(), String> {
+        std::iter::once(&mut self.active)
+            .chain(self.retired.iter_mut())
+            .try_for_each(KeyConf::load_secrets)
+    }
+}
+
 #[derive(Clone, Debug, Deserialize)]
 pub struct CipherConf {
-    pub key: String,
+    pub keyring: KeyringConf,
+    // HMAC key for the duplicate-detection index, kept apart from the keyring
+    pub blind_index: KeyConf,
+    // When set, the active key is rebuilt from shares at startup instead of loaded
+    pub unseal: Option<UnsealConf>,
     #[serde(default = "def_alg")]
     pub alg: Algorithm,
     pub alg_str: String,
 }
 
+#[derive(Clone, Debug, Deserialize)]
+pub struct UnsealConf {
+    pub threshold: u8,
+    pub port: u16,
+    #[serde(default = "def_unseal_bind")]
+    pub bind: IpAddr,
+    // SHA-256 of each share as printed by `keygen`; anything else is turned away
+    pub share_digests: Vec<String>,
+    pub tls: TlsConf,
+}
+
+// Clients must present a certificate issued by `client_ca`
+#[derive(Clone, Debug, Deserialize)]
+pub struct TlsConf {
+    pub cert: String,
+    pub key: SecretSource,
+    pub client_ca: String,
+}
+
+impl CipherConf {
+    pub fn load_secrets(&mut self) -> Result<(), String> {
+        self.keyring.load_secrets()?;
+        self.blind_index.load_secrets()
+    }
+}
+
 #[derive(Clone, Debug, Deserialize)]
 pub struct GrpcConf {
     pub port: u16,
+    // Without it the vault serves plaintext and cannot tell who uploaded a batch
+    pub tls: Option<TlsConf>,
+}
+
+#[derive(Clone, Debug, Deserialize)]
+pub struct ReservationConf {
+    pub default_ttl_secs: u32,
+    pub min_ttl_secs: u32,
+    pub max_ttl_secs: u32,
+    // How often lapsed holds are swept back into the pool
+    pub sweep_interval_secs: u32,
+}
+
+impl ReservationConf {
+    fn validate(&self) -> Result<(), String> {
+        if self.min_ttl_secs == 0 || self.min_ttl_secs > self.max_ttl_secs {
+            return Err("min_ttl_secs must be positive and not above max_ttl_secs".into());
+        }
+        if !(self.min_ttl_secs..=self.max_ttl_secs).contains(&self.default_ttl_secs) {
+            return Err("default_ttl_secs must lie between min_ttl_secs and max_ttl_secs".into());
+        }
+        if self.sweep_interval_secs == 0 {
+            return Err("sweep_interval_secs must be positive".into());
+        }
+        Ok(())
+    }
+
+    /// Hold duration for a request; 0 asks for the default, anything else is clamped.
+    pub fn ttl_secs(&self, requested: u32) -> u32 {
+        match requested {
+            0 => self.default_ttl_secs,
+            ttl => ttl.clamp(self.min_ttl_secs, self.max_ttl_secs),
+        }
+    }
 }
 
 #[derive(Clone, Debug, Deserialize)]
@@ -45,20 +237,63 @@ pub struct DatasourceConf {
 pub struct AppEnv {
     pub cipher: CipherConf,
     pub grpc: GrpcConf,
+    pub reservation: ReservationConf,
     pub registry: RegistryConf,
     pub app_name: String,
     pub datasource: DatasourceConf,
 }
 
 impl AppEnv {
-    pub fn from(conf_path: &str) -> Self {
+    pub fn from(conf_path: &str) -> Result<Self, Box<dyn std::error::Error>> {
         println!("Reading conf from {conf_path}");
         
         let yaml = fs::read_to_string(conf_path)
-            .expect("Unable to read config file!");
+            .map_err(|e| format!("Unable to read config file! {}", e))?;
         
-        let config: AppEnv = serde_yaml::from_str(&yaml)
-            .expect("Unable to parse config file!");
-        config
+        let mut config: AppEnv = serde_yaml::from_str(&yaml)
+            .map_err(|e| format!("Unable to parse config file! {}", e))?;
+
+        config.cipher.load_secrets()
+            .map_err(|e| format!("Unable to load cipher keys! {}", e))?;
+        config.reservation.validate()
+            .map_err(|e| format!("Invalid reservation config! {}", e))?;
+        Ok(config)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn reservation_conf() -> ReservationConf {
+        ReservationConf {
+            default_ttl_secs: 180,
+            min_ttl_secs: 30,
+            max_ttl_secs: 900,
+            sweep_interval_secs: 30,
+        }
+    }
+
+    #[test]
+    fn ttl_secs_defaults_and_clamps() {
+        let conf = reservation_conf();
+        assert_eq!(conf.ttl_secs(0), 180);
+        assert_eq!(conf.ttl_secs(1), 30);
+        assert_eq!(conf.ttl_secs(300), 300);
+        assert_eq!(conf.ttl_secs(u32::MAX), 900);
+    }
+
+    #[test]
+    fn validate_rejects_inconsistent_bounds() {
+        assert!(reservation_conf().validate().is_ok());
+        for conf in [
+            ReservationConf { min_ttl_secs: 0, ..reservation_conf() },
+            ReservationConf { min_ttl_secs: 901, ..reservation_conf() },
+            ReservationConf { default_ttl_secs: 10, ..reservation_conf() },
+            ReservationConf { default_ttl_secs: 1000, ..reservation_conf() },
+            ReservationConf { sweep_interval_secs: 0, ..reservation_conf() },
+        ] {
+            assert!(conf.validate().is_err());
+        }
     }
 }
@@ -1,8 +1,9 @@
 use std::net::SocketAddr;
-use tonic::transport::Server;
+use tonic::transport::{Certificate, Identity, Server, ServerTlsConfig};
 use crate::vault::pin_code_vault_service_server::PinCodeVaultServiceServer;
 use crate::pincode::service::RustPinCodeVault;
 use crate::application::AppContext;
+use crate::application::env::TlsConf;
 
 
 pub async fn run_grpc_server(context: &AppContext) -> Result<(), Box<dyn std::error::Error + Send + Sync>> {
@@ -13,7 +14,11 @@ pub async fn run_grpc_server(context: &AppContext) -> Result<(), Box<dyn std::er
 
     println!("Vault gRPC server running at {}", addr);
 
-    Server::builder()
+    let mut server = Server::builder();
+    if let Some(tls) = &context.env.grpc.tls {
+        server = server.tls_config(tls_config(tls)?)?;
+    }
+    server
         .add_service(PinCodeVaultServiceServer::new(service))
         .serve(addr)
         .await?;
@@ -26,3 +31,14 @@ pub fn run_grpc_server_bl(context: &AppContext) -> tokio::task::JoinHandle<Resul
         run_grpc_server(&context).await
     }) 
 }
+
+/// Mutual TLS for a listener: it presents `cert` and only accepts clients whose
+/// certificate was issued by `client_ca`.
+pub fn tls_config(conf: &TlsConf) -> Result<ServerTlsConfig, String> {
+    let read = |path: &str| std::fs::read(path).map_err(|e| format!("cannot read {}: {}", path, e));
+    let key = conf.key.load()?;
+    let key = key.expose().ok_or("TLS key was not loaded")?;
+    Ok(ServerTlsConfig::new()
+        .identity(Identity::from_pem(read(&conf.cert)?, key))
+        .client_ca_root(Certificate::from_pem(read(&conf.client_ca)?)))
+}
@@ -1,51 +1,79 @@
 
 use crate::application::database::{DatabaseClient};
 use crate::application::registry::client::EurekaRegisteryClient;
-use crate::cipher::{Cipher, Algorithm};
-use crate::cipher::aes::Aes256Cipher;
+use crate::cipher::{selftest, Cipher, CipherError, Algorithm};
+use crate::cipher::blind_index::BlindIndex;
+use crate::cipher::aes::{Aes128Cipher, Aes256Cipher, Aes256GcmSivCipher};
+use crate::cipher::chacha::{ChaCha20Cipher, XChaCha20Cipher};
 use crate::application::env::AppEnv;
+use crate::pincode::{canary, sweeper};
+use crate::pincode::model::repository::PinCodeRepository;
 use std::sync::Arc;
 
 pub mod grpc;
 pub mod env;
 pub mod registry;
 pub mod database;
+pub mod cli;
+pub mod unseal;
 
 #[derive(Clone)]
 pub struct AppContext {
     pub cipher: Option<Arc<dyn Cipher + Send + Sync>>,
+    pub blind_index: BlindIndex,
     pub env: AppEnv,
     pub db_client: DatabaseClient,
 }
 
 impl AppContext {
-    pub fn new(env: &AppEnv) -> Self {
+    pub fn new(env: &AppEnv) -> Result<Self, CipherError> {
         let cipher: Option<Arc<dyn Cipher + Send + Sync>> = match env.cipher.alg {
-            Algorithm::Aes256Gcm => Some(Arc::new(Aes256Cipher::new(env))),
-            _ => None,
+            Algorithm::Aes128Gcm => Some(Arc::new(Aes128Cipher::new(env)?)),
+            Algorithm::Aes256Gcm => Some(Arc::new(Aes256Cipher::new(env)?)),
+            Algorithm::Aes256GcmSiv => Some(Arc::new(Aes256GcmSivCipher::new(env)?)),
+            Algorithm::ChaCha20Poly1305 => Some(Arc::new(ChaCha20Cipher::new(env)?)),
+            Algorithm::XChaCha20Poly1305 => Some(Arc::new(XChaCha20Cipher::new(env)?)),
         };
+        let blind_index = BlindIndex::new(&env.cipher.blind_index)?;
         let db_client = DatabaseClient::new(env);
 
-        Self {
+        Ok(Self {
             cipher,
+            blind_index,
             env: env.clone(),
-            db_client: db_client
-        }
+            db_client
+        })
     }
 }
 
-pub async fn boot() -> AppContext{
+pub async fn boot() -> Result<AppContext, Box<dyn std::error::Error>> {
 
     let root_dir = std::env::current_dir().expect("Error"); 
     let config_path = root_dir.join("config.yml");
-    let env = AppEnv::from(&config_path.to_str().unwrap());
-    let mut context = AppContext::new(&env);
-    let _ = context.db_client.init().await;
+    let mut env = AppEnv::from(config_path.to_str().unwrap())?;
+    if let Some(conf) = env.cipher.unseal.clone() {
+        let active = &mut env.cipher.keyring.active;
+        if active.key.is_some() || active.passphrase.is_some() {
+            return Err(format!("key {} must not be configured when starting sealed", active.id).into());
+        }
+        // Nothing else, gRPC included, starts before the quorum is reached
+        active.key = Some(unseal::key_source(&unseal::wait_for_key(&conf).await?));
+    }
+    let mut context = AppContext::new(&env)?;
+    let cipher = context.cipher.as_ref().ok_or(CipherError::NotInitialized)?;
+    selftest::run(&env.cipher.alg, cipher.as_ref())?;
+    println!("Cipher self-test passed for {:?}", env.cipher.alg);
+
+    context.db_client.init().await?;
+    // Never serve with keys that cannot open what is already stored
+    canary::verify(&context).await?;
+    PinCodeRepository::new(&context).ensure_indexes().await?;
+    sweeper::start(&context);
 
     let registy = EurekaRegisteryClient::new(&env);
     registy.start();
 
 
     grpc::run_grpc_server_bl(&context);
-    context
+    Ok(context)
 }
\ No newline at end of file
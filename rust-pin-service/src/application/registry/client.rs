@@ -45,7 +45,7 @@ impl EurekaRegisteryClient {
             },
             data_center_info: DataCenterInfo {
                 class: "com.netflix.appinfo.InstanceInfo$DefaultDataCenterInfo".into(),
-                name: self.data_center_info_name.clone().into(),
+                name: self.data_center_info_name.clone(),
             },
         }
     }
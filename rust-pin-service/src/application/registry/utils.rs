@@ -34,7 +34,7 @@ pub async fn register_in_eureka(
     Err(format!("Eureka registration failed after {attempts} attempts").into())
 }
 
-pub async fn heartbeat(client: &Client, instance_id: &String, app_name: &String, eureka_base: &String) -> Result<(), Box<dyn std::error::Error>> {
+pub async fn heartbeat(client: &Client, instance_id: &str, app_name: &str, eureka_base: &str) -> Result<(), Box<dyn std::error::Error>> {
     let res = client
             .put(format!("{}/apps/{}/{}", eureka_base, app_name.to_uppercase(), instance_id))
             .send()
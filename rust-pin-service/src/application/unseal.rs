@@ -0,0 +1,147 @@
+use std::fmt::Write;
+use std::net::SocketAddr;
+use std::sync::Arc;
+
+use tokio::sync::{oneshot, Mutex};
+use tonic::transport::Server;
+use tonic::{Request, Response, Status};
+
+use crate::application::env::{SecretSource, UnsealConf};
+use crate::application::grpc::tls_config;
+use crate::cipher::{shamir, SecretBytes, SecretString};
+use crate::vault::vault_unseal_service_server::{VaultUnsealService, VaultUnsealServiceServer};
+use crate::vault::{UnsealShareRequest, UnsealStatusResponse};
+
+struct UnsealState {
+    shares: Vec<SecretBytes>,
+    // Taken once the key is rebuilt; `None` means unsealed
+    key: Option<oneshot::Sender<SecretBytes>>,
+}
+
+pub struct RustVaultUnseal {
+    threshold: u8,
+    share_digests: Vec<String>,
+    state: Arc<Mutex<UnsealState>>,
+}
+
+impl RustVaultUnseal {
+    fn status(&self, state: &UnsealState, message: String) -> UnsealStatusResponse {
+        UnsealStatusResponse {
+            sealed: state.key.is_some(),
+            received: state.shares.len() as i32,
+            threshold: self.threshold as i32,
+            message,
+        }
+    }
+}
+
+#[tonic::async_trait]
+impl VaultUnsealService for RustVaultUnseal {
+    async fn submit_unseal_share(
+        &self,
+        request: Request<UnsealShareRequest>,
+    ) -> Result<Response<UnsealStatusResponse>, Status> {
+        let share = shamir::decode_share(&request.into_inner().share)?;
+        // Checked before the share is kept, so junk can neither take an index nor spoil the set
+        if !self.share_digests.contains(&shamir::share_digest(&share)) {
+            return Err(Status::permission_denied("Share does not belong to this vault"));
+        }
+        let mut state = self.state.lock().await;
+
+        if state.key.is_none() {
+            return Ok(Response::new(self.status(&state, "Vault is already unsealed".into())));
+        }
+        let index = shamir::share_index(&share);
+        if state.shares.iter().any(|s| shamir::share_index(s) == index) {
+            return Err(Status::already_exists(format!("Share {} was already submitted", index)));
+        }
+        state.shares.push(share);
+        println!("Unseal share {} accepted ({}/{})", index, state.shares.len(), self.threshold);
+
+        if state.shares.len() < self.threshold as usize {
+            let message = format!("Share accepted, {} more needed", self.threshold as usize - state.shares.len());
+            return Ok(Response::new(self.status(&state, message)));
+        }
+
+        match shamir::combine(&state.shares, self.threshold) {
+            Ok(key) => {
+                if let Some(sender) = state.key.take() {
+                    let _ = sender.send(key);
+                }
+                state.shares.clear();
+                println!("Vault unsealed");
+                Ok(Response::new(self.status(&state, "Vault unsealed".into())))
+            }
+            Err(e) => {
+                // Every share matched a digest, so the digests themselves come from
+                // different keygen runs; nothing submitted so far can be trusted
+                state.shares.clear();
+                println!("Unseal attempt failed: {}", e);
+                Err(Status::invalid_argument(format!("{}; all shares were discarded, start over", e)))
+            }
+        }
+    }
+
+    async fn get_unseal_status(
+        &self,
+        _request: Request<()>,
+    ) -> Result<Response<UnsealStatusResponse>, Status> {
+        let state = self.state.lock().await;
+        let message = if state.key.is_some() { "Vault is sealed" } else { "Vault is unsealed" };
+        Ok(Response::new(self.status(&state, message.into())))
+    }
+}
+
+/// Serves only the unseal RPC, over mutual TLS, until `threshold` shares rebuild
+/// the master key, then stops that listener and returns the key.
+pub async fn wait_for_key(conf: &UnsealConf) -> Result<SecretBytes, Box<dyn std::error::Error>> {
+    if conf.threshold < 2 {
+        return Err("unseal threshold must be at least 2".into());
+    }
+    if conf.share_digests.len() < conf.threshold as usize {
+        return Err("unseal share_digests must list at least `threshold` shares".into());
+    }
+    let addr = SocketAddr::new(conf.bind, conf.port);
+    let tls = tls_config(&conf.tls)?;
+    let (key_tx, key_rx) = oneshot::channel();
+    let (stop_tx, stop_rx) = oneshot::channel::<()>();
+
+    let service = RustVaultUnseal {
+        threshold: conf.threshold,
+        share_digests: conf.share_digests.iter().map(|digest| digest.trim().to_lowercase()).collect(),
+        state: Arc::new(Mutex::new(UnsealState {
+            shares: Vec::new(),
+            key: Some(key_tx),
+        })),
+    };
+
+    println!("Vault is sealed, waiting for {} key shares on {}", conf.threshold, addr);
+    let server = tokio::spawn(
+        Server::builder()
+            .tls_config(tls)?
+            .add_service(VaultUnsealServiceServer::new(service))
+            .serve_with_shutdown(addr, async {
+                let _ = stop_rx.await;
+            }),
+    );
+
+    let Ok(key) = key_rx.await else {
+        // The sender only goes away with the server, so surface its error
+        server.await??;
+        return Err("unseal server stopped before the vault was unsealed".into());
+    };
+    let _ = stop_tx.send(());
+    server.await??;
+    Ok(key)
+}
+
+/// Hands a rebuilt key to the keyring as if it had been loaded from `key`.
+pub fn key_source(key: &SecretBytes) -> SecretSource {
+    // Sized up front so the encoded key is never reallocated and left behind
+    let mut encoded = SecretString::new(String::with_capacity(4 + key.expose().len() * 2));
+    encoded.expose_mut().push_str("hex:");
+    for byte in key.expose() {
+        let _ = write!(encoded.expose_mut(), "{:02x}", byte);
+    }
+    SecretSource::Loaded(encoded)
+}
@@ -1,72 +1,161 @@
-use aes_gcm::{Aes256Gcm, Nonce}; 
-use aes_gcm::aead::{Aead, KeyInit};
-use base64::{engine::general_purpose, Engine as _};
+use aes_gcm::{Aes128Gcm, Aes256Gcm};
+use aes_gcm_siv::Aes256GcmSiv;
 
 use crate::cipher::Algorithm;
+use crate::cipher::keyring::Keyring;
 use crate::application::env::AppEnv;
 
-use super::Cipher;
-use super::utils;
+use super::{Cipher, CipherError, SecretBytes};
+
+#[derive(Clone)]
+pub struct Aes128Cipher{
+    keyring: Keyring<Aes128Gcm>,
+}
+
+impl Aes128Cipher {
+    pub fn new(env: &AppEnv) -> Result<Self, CipherError> {
+        if let Algorithm::Aes128Gcm = env.cipher.alg {
+            let keyring = Keyring::new(&env.cipher.keyring)?;
+            Ok(Self { keyring })
+        } else {
+            panic!("Algorithm not supported: {:?}", env.cipher.alg_str);
+        }
+    }
+
+    pub fn from_key(key_id: &str, key: &[u8]) -> Result<Self, CipherError> {
+        Ok(Self { keyring: Keyring::single(key_id, key)? })
+    }
+}
+
+impl Cipher for Aes128Cipher {
+
+    fn clone_box(&self) -> Box<dyn Cipher> {
+        Box::new(self.clone())
+    }
+
+    fn encrypt_with_aad(&self, pin: &[u8], aad: &[u8]) -> Result<Vec<u8>, CipherError> {
+        self.keyring.seal(pin, aad)
+    }
+
+    fn decrypt_with_aad(&self, data: &[u8], aad: &[u8]) -> Result<SecretBytes, CipherError> {
+        self.keyring.open(data, aad)
+    }
+
+    fn is_current(&self, data: &[u8]) -> bool {
+        self.keyring.is_current(data)
+    }
+}
 
 #[derive(Clone)]
 pub struct Aes256Cipher{
-    // key: Key<Aes256Gcm>,
-    cipher: Aes256Gcm,
+    keyring: Keyring<Aes256Gcm>,
 }
 
 impl Aes256Cipher {
-    pub fn new(env: &AppEnv) -> Self {
+    pub fn new(env: &AppEnv) -> Result<Self, CipherError> {
         if let Algorithm::Aes256Gcm = env.cipher.alg {
-            let key = utils::ensure_key_len_for_aes256(env.cipher.key.clone());
-            let cipher = Aes256Gcm::new(&key);
-            Self { 
-                // key, 
-                cipher }
+            let keyring = Keyring::new(&env.cipher.keyring)?;
+            Ok(Self { keyring })
         } else {
             panic!("Algorithm not supported: {:?}", env.cipher.alg_str);
         }
     }
+
+    pub fn from_key(key_id: &str, key: &[u8]) -> Result<Self, CipherError> {
+        Ok(Self { keyring: Keyring::single(key_id, key)? })
+    }
 }
 
 impl Cipher for Aes256Cipher {
-    
+
+    fn clone_box(&self) -> Box<dyn Cipher> {
+        Box::new(self.clone())
+    }
+
+    fn encrypt_with_aad(&self, pin: &[u8], aad: &[u8]) -> Result<Vec<u8>, CipherError> {
+        self.keyring.seal(pin, aad)
+    }
+
+    fn decrypt_with_aad(&self, data: &[u8], aad: &[u8]) -> Result<SecretBytes, CipherError> {
+        // The key is picked from the key ID in the envelope
+        self.keyring.open(data, aad)
+    }
+
+    fn is_current(&self, data: &[u8]) -> bool {
+        self.keyring.is_current(data)
+    }
+}
+
+// GCM-SIV derives its keystream from the nonce and the message, so a repeated
+// random nonce only reveals that two PINs are equal instead of leaking keystream.
+#[derive(Clone)]
+pub struct Aes256GcmSivCipher{
+    keyring: Keyring<Aes256GcmSiv>,
+}
+
+impl Aes256GcmSivCipher {
+    pub fn new(env: &AppEnv) -> Result<Self, CipherError> {
+        if let Algorithm::Aes256GcmSiv = env.cipher.alg {
+            let keyring = Keyring::new(&env.cipher.keyring)?;
+            Ok(Self { keyring })
+        } else {
+            panic!("Algorithm not supported: {:?}", env.cipher.alg_str);
+        }
+    }
+
+    pub fn from_key(key_id: &str, key: &[u8]) -> Result<Self, CipherError> {
+        Ok(Self { keyring: Keyring::single(key_id, key)? })
+    }
+}
+
+impl Cipher for Aes256GcmSivCipher {
+
     fn clone_box(&self) -> Box<dyn Cipher> {
         Box::new(self.clone())
     }
-    
-    fn encrypt(&self, pin: &[u8]) -> Vec<u8> { 
-        let nonce = utils::generate_nonce();
-        let mut ciphertext = self.cipher
-            .encrypt(&nonce, pin)
-            .expect("encryption failure!");
-        let mut result = nonce.to_vec();
-        result.append(&mut ciphertext);
-        result
-    }
-    
-    fn decrypt(&self, data: &[u8]) -> Vec<u8> {
-        // Separate nonce and ciphertext
-        let (nonce_bytes, ciphertext) = data.split_at(12); // AES-GCM expects 12-byte nonce
-
-        let nonce = Nonce::from_slice(nonce_bytes); // 12-byte nonce
-        let plaintext = self.cipher
-            .decrypt(nonce, ciphertext) // pass nonce by reference
-            .expect("decryption failure!");
-
-        plaintext
-    }
-    fn enc_encrypt(&self, pin: String) -> String {
-        let encrypted_pin = self.encrypt(pin.as_bytes());
-        general_purpose::STANDARD.encode(encrypted_pin)
-    }
-
-    fn enc_decrypt(&self, data: String) -> String {
-        let decoded_data = general_purpose::STANDARD.decode(&data)
-            .expect("Base64 decode failed");
-        let decrypted_bytes = self.decrypt(&decoded_data);
-        // Convert decrypted bytes back to String (assuming UTF-8)
-        str::from_utf8(&decrypted_bytes)
-            .expect("UTF-8 conversion failed")
-            .to_string()
-    }
-}
\ No newline at end of file
+
+    fn encrypt_with_aad(&self, pin: &[u8], aad: &[u8]) -> Result<Vec<u8>, CipherError> {
+        self.keyring.seal(pin, aad)
+    }
+
+    fn decrypt_with_aad(&self, data: &[u8], aad: &[u8]) -> Result<SecretBytes, CipherError> {
+        self.keyring.open(data, aad)
+    }
+
+    fn is_current(&self, data: &[u8]) -> bool {
+        self.keyring.is_current(data)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    const PIN: &[u8] = b"1234567890123456";
+
+    fn key(len: u8) -> Vec<u8> {
+        (0..len).collect()
+    }
+
+    // Round-trips a PIN, then flips a tag bit and swaps the AAD
+    fn assert_seals(cipher: &dyn Cipher) {
+        let sealed = cipher.encrypt_with_aad(PIN, b"pincode:a").unwrap();
+        assert!(cipher.is_current(&sealed));
+        assert_eq!(cipher.decrypt_with_aad(&sealed, b"pincode:a").unwrap().expose(), PIN);
+
+        let mut tampered = sealed.clone();
+        *tampered.last_mut().unwrap() ^= 1;
+        assert!(matches!(cipher.decrypt_with_aad(&tampered, b"pincode:a"), Err(CipherError::Authentication)));
+        assert!(matches!(cipher.decrypt_with_aad(&sealed, b"pincode:b"), Err(CipherError::Authentication)));
+    }
+
+    #[test]
+    fn aes128_round_trips_and_rejects_tampering() {
+        assert_seals(&Aes128Cipher::from_key("k1", &key(16)).unwrap());
+    }
+
+    #[test]
+    fn aes256_gcm_siv_round_trips_and_rejects_tampering() {
+        assert_seals(&Aes256GcmSivCipher::from_key("k1", &key(32)).unwrap());
+    }
+}
@@ -0,0 +1,67 @@
+use hmac::{Hmac, Mac};
+use sha2::Sha256;
+
+use crate::application::env::KeyConf;
+
+use super::{kdf, CipherError};
+
+const KEY_LEN: usize = 32;
+
+/// Keyed HMAC-SHA256 of a plaintext PIN. Equal PINs give equal digests, so a
+/// unique index catches duplicates without the plaintext being stored. The key
+/// is separate from the keyring and cannot be rotated without rebuilding the index.
+#[derive(Clone)]
+pub struct BlindIndex {
+    mac: Hmac<Sha256>,
+}
+
+impl BlindIndex {
+    pub fn new(conf: &KeyConf) -> Result<Self, CipherError> {
+        let key = kdf::derive_key(conf, KEY_LEN)?;
+        let mac = Hmac::<Sha256>::new_from_slice(key.expose()).map_err(|_| CipherError::InvalidKey {
+            id: conf.id.clone(),
+            reason: "wrong key length".into(),
+        })?;
+        Ok(Self { mac })
+    }
+
+    pub fn compute(&self, pin: &str) -> String {
+        let mut mac = self.mac.clone();
+        mac.update(pin.as_bytes());
+        hex::encode(mac.finalize().into_bytes())
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::application::env::SecretSource;
+
+    fn index(seed: u8) -> BlindIndex {
+        let key: Vec<u8> = (0..KEY_LEN as u8).map(|i| i.wrapping_add(seed)).collect();
+        BlindIndex::new(&KeyConf {
+            id: "blind".into(),
+            key: Some(SecretSource::Loaded(format!("hex:{}", hex::encode(key)).into())),
+            passphrase: None,
+            salt: None,
+            kdf: Default::default(),
+        })
+        .unwrap()
+    }
+
+    #[test]
+    fn same_pin_gives_same_index() {
+        assert_eq!(index(1).compute("1234567890123456"), index(1).compute("1234567890123456"));
+    }
+
+    #[test]
+    fn different_keys_give_different_indexes() {
+        assert_ne!(index(1).compute("1234567890123456"), index(2).compute("1234567890123456"));
+    }
+
+    #[test]
+    fn different_pins_give_different_indexes() {
+        let index = index(1);
+        assert_ne!(index.compute("1234567890123456"), index.compute("1234567890123457"));
+    }
+}
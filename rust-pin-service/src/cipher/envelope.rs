@@ -0,0 +1,87 @@
+// Versioned ciphertext layout: `version || key_id_len || key_id || nonce || ciphertext`.
+// Ciphertexts produced before key IDs existed are a bare `nonce || ciphertext`.
+//
+// Version 1 is sealed without associated data, version 2 is bound to the AAD the
+// caller passed in. The header itself is not authenticated, but rewriting 2 to 1
+// only makes the tag check fail.
+
+pub const VERSION: u8 = 1;
+pub const VERSION_AAD: u8 = 2;
+pub const MAX_KEY_ID_LEN: usize = u8::MAX as usize;
+
+pub struct Envelope<'a> {
+    pub version: u8,
+    pub key_id: &'a str,
+    pub payload: &'a [u8],
+}
+
+pub fn wrap(version: u8, key_id: &str, payload: &[u8]) -> Vec<u8> {
+    let id = key_id.as_bytes();
+    let mut result = Vec::with_capacity(2 + id.len() + payload.len());
+    result.push(version);
+    result.push(id.len() as u8);
+    result.extend_from_slice(id);
+    result.extend_from_slice(payload);
+    result
+}
+
+// Whether `data` is an envelope sealed against the caller's AAD
+pub fn is_bound(data: &[u8]) -> bool {
+    parse(data).is_some_and(|env| env.version == VERSION_AAD)
+}
+
+pub fn parse(data: &[u8]) -> Option<Envelope<'_>> {
+    let (&version, rest) = data.split_first()?;
+    if version != VERSION && version != VERSION_AAD {
+        return None;
+    }
+    let (&id_len, rest) = rest.split_first()?;
+    let id_len = id_len as usize;
+    if id_len == 0 || rest.len() < id_len {
+        return None;
+    }
+    let (id, payload) = rest.split_at(id_len);
+    let key_id = std::str::from_utf8(id).ok()?;
+    Some(Envelope { version, key_id, payload })
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn parse_round_trips_wrap() {
+        for version in [VERSION, VERSION_AAD] {
+            let data = wrap(version, "k2", b"nonce-and-ciphertext");
+            let envelope = parse(&data).expect("wrapped envelope parses");
+            assert_eq!(envelope.version, version);
+            assert_eq!(envelope.key_id, "k2");
+            assert_eq!(envelope.payload, b"nonce-and-ciphertext");
+        }
+    }
+
+    #[test]
+    fn parse_rejects_truncated_input() {
+        let data = wrap(VERSION_AAD, "key-1", b"payload");
+        assert!(parse(&[]).is_none());
+        assert!(parse(&data[..1]).is_none());
+        // Key ID length says 5 bytes but fewer follow
+        assert!(parse(&data[..4]).is_none());
+        // An empty key ID is never written
+        assert!(parse(&[VERSION, 0, 1, 2, 3]).is_none());
+    }
+
+    #[test]
+    fn parse_rejects_unknown_version() {
+        let mut data = wrap(VERSION, "k1", b"payload");
+        for version in [0, VERSION_AAD + 1, u8::MAX] {
+            data[0] = version;
+            assert!(parse(&data).is_none());
+        }
+    }
+
+    #[test]
+    fn parse_rejects_non_utf8_key_id() {
+        assert!(parse(&[VERSION, 2, 0xff, 0xfe, 1, 2, 3]).is_none());
+    }
+}
@@ -0,0 +1,77 @@
+use std::fmt;
+
+use tonic::Status;
+
+#[derive(Debug)]
+pub enum CipherError {
+    /// Input is shorter than a nonce plus an authentication tag.
+    ShortInput { len: usize, min: usize },
+    /// Input is not valid standard base64.
+    Base64(base64::DecodeError),
+    /// Tag verification failed: wrong key or tampered ciphertext.
+    Authentication,
+    /// The envelope names a key ID that is not in the keyring.
+    UnknownKey(String),
+    /// Plaintext decrypted fine but is not valid UTF-8.
+    Utf8(std::str::Utf8Error),
+    /// The AEAD backend refused to encrypt the plaintext.
+    Encryption,
+    /// No cipher was configured for this service.
+    NotInitialized,
+    /// Configured key material is malformed or too weak to use.
+    InvalidKey { id: String, reason: String },
+    /// A startup self-test failed, or the keys do not open the existing vault.
+    SelfTest(String),
+    /// An unseal key share is malformed, or the shares do not rebuild a valid key.
+    InvalidShare(String),
+    /// A record ciphertext was sealed without AAD and the keyring requires it.
+    Unbound,
+}
+
+impl fmt::Display for CipherError {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            CipherError::ShortInput { len, min } => {
+                write!(f, "ciphertext too short: {} bytes, expected at least {}", len, min)
+            }
+            CipherError::Base64(e) => write!(f, "invalid base64: {}", e),
+            CipherError::Authentication => write!(f, "authentication tag mismatch"),
+            CipherError::UnknownKey(id) => write!(f, "unknown key id: {}", id),
+            CipherError::Utf8(e) => write!(f, "decrypted data is not valid UTF-8: {}", e),
+            CipherError::Encryption => write!(f, "encryption failure"),
+            CipherError::NotInitialized => write!(f, "Cipher not initialized"),
+            CipherError::Unbound => write!(f, "ciphertext is not bound to its record"),
+            CipherError::InvalidKey { id, reason } => write!(f, "invalid key {}: {}", id, reason),
+            CipherError::SelfTest(reason) => write!(f, "cipher self-test failed: {}", reason),
+            CipherError::InvalidShare(reason) => write!(f, "invalid key share: {}", reason),
+        }
+    }
+}
+
+impl std::error::Error for CipherError {}
+
+impl From<base64::DecodeError> for CipherError {
+    fn from(e: base64::DecodeError) -> Self {
+        CipherError::Base64(e)
+    }
+}
+
+impl From<std::str::Utf8Error> for CipherError {
+    fn from(e: std::str::Utf8Error) -> Self {
+        CipherError::Utf8(e)
+    }
+}
+
+impl From<CipherError> for Status {
+    fn from(e: CipherError) -> Self {
+        match e {
+            CipherError::Encryption
+            | CipherError::NotInitialized
+            | CipherError::InvalidKey { .. }
+            | CipherError::SelfTest(_) => {
+                Status::internal(e.to_string())
+            }
+            _ => Status::invalid_argument(e.to_string()),
+        }
+    }
+}
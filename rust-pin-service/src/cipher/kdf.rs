@@ -0,0 +1,183 @@
+use argon2::Argon2;
+use base64::{engine::general_purpose, Engine as _};
+use hkdf::Hkdf;
+use serde::Deserialize;
+use sha2::Sha256;
+
+use crate::application::env::{KeyConf, SecretSource};
+
+use super::{CipherError, SecretBytes};
+
+const MIN_SALT_LEN: usize = 16;
+const MIN_PASSPHRASE_LEN: usize = 16;
+const HKDF_INFO: &[u8] = b"topup-cipher-vault/pin-key";
+
+#[derive(Clone, Debug, Default, Deserialize)]
+#[serde(rename_all = "lowercase")]
+pub enum Kdf {
+    #[default]
+    Argon2id,
+    // Only suitable when the passphrase is already a high-entropy secret
+    Hkdf,
+}
+
+/// Resolves the configured key material into exactly `len` raw key bytes.
+///
+/// `key` must be `hex:` or `base64:` encoded and decode to `len` bytes. Otherwise a
+/// `passphrase` and `salt` are run through the configured KDF.
+pub fn derive_key(conf: &KeyConf, len: usize) -> Result<SecretBytes, CipherError> {
+    let invalid = |reason: String| CipherError::InvalidKey {
+        id: conf.id.clone(),
+        reason,
+    };
+
+    let key = conf.key.as_ref().map(expose).transpose().map_err(invalid)?;
+    let passphrase = conf.passphrase.as_ref().map(expose).transpose().map_err(invalid)?;
+
+    match (key, passphrase) {
+        (Some(_), Some(_)) => Err(invalid("set either key or passphrase, not both".into())),
+        (None, None) => Err(invalid("no key or passphrase configured".into())),
+        (Some(key), None) => {
+            let bytes = SecretBytes::new(decode(key).map_err(invalid)?);
+            let raw = bytes.expose();
+            if raw.len() != len {
+                return Err(invalid(format!("expected {} key bytes, got {}", len, raw.len())));
+            }
+            if raw.iter().all(|b| *b == raw[0]) {
+                return Err(invalid("key bytes are all identical".into()));
+            }
+            Ok(bytes)
+        }
+        (None, Some(passphrase)) => {
+            if passphrase.chars().count() < MIN_PASSPHRASE_LEN {
+                return Err(invalid(format!(
+                    "passphrase must be at least {} characters",
+                    MIN_PASSPHRASE_LEN
+                )));
+            }
+            let salt = conf
+                .salt
+                .as_deref()
+                .ok_or_else(|| invalid("passphrase requires a salt".into()))
+                .and_then(|salt| decode(salt).map_err(invalid))?;
+            if salt.len() < MIN_SALT_LEN {
+                return Err(invalid(format!("salt must be at least {} bytes", MIN_SALT_LEN)));
+            }
+
+            let mut key = SecretBytes::new(vec![0u8; len]);
+            match conf.kdf {
+                Kdf::Argon2id => Argon2::default()
+                    .hash_password_into(passphrase.as_bytes(), &salt, key.expose_mut())
+                    .map_err(|e| invalid(format!("argon2id failed: {}", e)))?,
+                Kdf::Hkdf => Hkdf::<Sha256>::new(Some(&salt), passphrase.as_bytes())
+                    .expand(HKDF_INFO, key.expose_mut())
+                    .map_err(|e| invalid(format!("hkdf failed: {}", e)))?,
+            }
+            Ok(key)
+        }
+    }
+}
+
+fn expose(source: &SecretSource) -> Result<&str, String> {
+    source
+        .expose()
+        .ok_or_else(|| format!("secret {:?} was not loaded", source))
+}
+
+fn decode(value: &str) -> Result<Vec<u8>, String> {
+    if let Some(hex_str) = value.strip_prefix("hex:") {
+        hex::decode(hex_str).map_err(|e| format!("invalid hex: {}", e))
+    } else if let Some(b64) = value.strip_prefix("base64:") {
+        general_purpose::STANDARD
+            .decode(b64)
+            .map_err(|e| format!("invalid base64: {}", e))
+    } else {
+        Err("value must be prefixed with hex: or base64:".into())
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn key_conf(key: Option<&str>, passphrase: Option<&str>, salt: Option<&str>, kdf: Kdf) -> KeyConf {
+        let loaded = |value: &str| SecretSource::Loaded(value.to_string().into());
+        KeyConf {
+            id: "test".into(),
+            key: key.map(loaded),
+            passphrase: passphrase.map(loaded),
+            salt: salt.map(str::to_string),
+            kdf,
+        }
+    }
+
+    fn rejection(conf: &KeyConf, len: usize) -> String {
+        match derive_key(conf, len).unwrap_err() {
+            CipherError::InvalidKey { reason, .. } => reason,
+            e => panic!("unexpected error: {}", e),
+        }
+    }
+
+    #[test]
+    fn decodes_raw_keys_of_the_right_length() {
+        let hex_key = format!("hex:{}", hex::encode((0u8..32).collect::<Vec<_>>()));
+        let key = derive_key(&key_conf(Some(&hex_key), None, None, Kdf::default()), 32).unwrap();
+        assert_eq!(key.expose(), &(0u8..32).collect::<Vec<_>>());
+
+        let b64_key = format!("base64:{}", general_purpose::STANDARD.encode((0u8..16).collect::<Vec<_>>()));
+        let key = derive_key(&key_conf(Some(&b64_key), None, None, Kdf::default()), 16).unwrap();
+        assert_eq!(key.expose().len(), 16);
+    }
+
+    #[test]
+    fn rejects_raw_keys_of_the_wrong_length() {
+        let hex_key = format!("hex:{}", hex::encode((0u8..16).collect::<Vec<_>>()));
+        let conf = key_conf(Some(&hex_key), None, None, Kdf::default());
+        assert_eq!(rejection(&conf, 32), "expected 32 key bytes, got 16");
+
+        let b64_key = format!("base64:{}", general_purpose::STANDARD.encode((0u8..33).collect::<Vec<_>>()));
+        let conf = key_conf(Some(&b64_key), None, None, Kdf::default());
+        assert_eq!(rejection(&conf, 32), "expected 32 key bytes, got 33");
+    }
+
+    #[test]
+    fn rejects_keys_of_identical_bytes() {
+        let hex_key = format!("hex:{}", "00".repeat(32));
+        let conf = key_conf(Some(&hex_key), None, None, Kdf::default());
+        assert_eq!(rejection(&conf, 32), "key bytes are all identical");
+    }
+
+    #[test]
+    fn rejects_unprefixed_or_malformed_keys() {
+        let conf = key_conf(Some(&"ab".repeat(32)), None, None, Kdf::default());
+        assert_eq!(rejection(&conf, 32), "value must be prefixed with hex: or base64:");
+
+        let conf = key_conf(Some("hex:zz"), None, None, Kdf::default());
+        assert!(rejection(&conf, 32).starts_with("invalid hex"));
+    }
+
+    #[test]
+    fn requires_exactly_one_of_key_or_passphrase() {
+        let hex_key = format!("hex:{}", hex::encode((0u8..32).collect::<Vec<_>>()));
+        let both = key_conf(Some(&hex_key), Some("a long enough passphrase"), None, Kdf::default());
+        assert_eq!(rejection(&both, 32), "set either key or passphrase, not both");
+
+        let neither = key_conf(None, None, None, Kdf::default());
+        assert_eq!(rejection(&neither, 32), "no key or passphrase configured");
+    }
+
+    #[test]
+    fn derives_the_same_key_from_the_same_passphrase() {
+        let salt = format!("hex:{}", "5a".repeat(16));
+        let conf = key_conf(None, Some("a long enough passphrase"), Some(&salt), Kdf::Hkdf);
+        let first = derive_key(&conf, 32).unwrap();
+        let second = derive_key(&conf, 32).unwrap();
+        assert_eq!(first.expose(), second.expose());
+
+        let short_salt = key_conf(None, Some("a long enough passphrase"), Some("hex:00ff"), Kdf::Hkdf);
+        assert_eq!(rejection(&short_salt, 32), "salt must be at least 16 bytes");
+
+        let short = key_conf(None, Some("too short"), Some(&salt), Kdf::Hkdf);
+        assert_eq!(rejection(&short, 32), "passphrase must be at least 16 characters");
+    }
+}
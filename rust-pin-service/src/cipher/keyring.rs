@@ -0,0 +1,196 @@
+use std::collections::HashMap;
+
+use aes::cipher::Unsigned;
+use aes_gcm::aead::{Aead, AeadCore, KeyInit, Nonce, Payload};
+
+use crate::application::env::KeyringConf;
+
+use super::{envelope, kdf, utils, CipherError, SecretBytes};
+
+/// One AEAD instance per configured key, indexed by key ID.
+/// New ciphertexts are always sealed under the active key; retired keys only open.
+#[derive(Clone)]
+pub struct Keyring<A> {
+    active_id: String,
+    keys: HashMap<String, A>,
+    require_aad: bool,
+}
+
+impl<A> Keyring<A>
+where
+    A: Aead + AeadCore + Clone,
+{
+    pub fn new(conf: &KeyringConf) -> Result<Self, CipherError>
+    where
+        A: KeyInit,
+    {
+        let mut keys = HashMap::new();
+        for key in std::iter::once(&conf.active).chain(conf.retired.iter()) {
+            let bytes = kdf::derive_key(key, A::key_size())?;
+            if keys.insert(key.id.clone(), init(&key.id, bytes.expose())?).is_some() {
+                return Err(CipherError::InvalidKey {
+                    id: key.id.clone(),
+                    reason: "duplicate key id".into(),
+                });
+            }
+        }
+        Ok(Self {
+            active_id: conf.active.id.clone(),
+            keys,
+            require_aad: conf.require_aad,
+        })
+    }
+
+    /// Ring holding a single raw key, as used for per-batch data keys.
+    pub fn single(key_id: &str, key: &[u8]) -> Result<Self, CipherError>
+    where
+        A: KeyInit,
+    {
+        let mut keys = HashMap::new();
+        keys.insert(key_id.to_string(), init(key_id, key)?);
+        // Data keys postdate AAD, so nothing sealed under one is unbound
+        Ok(Self {
+            active_id: key_id.to_string(),
+            keys,
+            require_aad: true,
+        })
+    }
+
+    pub fn active_id(&self) -> &str {
+        &self.active_id
+    }
+
+    // Current means sealed under the active key and bound to its record
+    pub fn is_current(&self, data: &[u8]) -> bool {
+        envelope::parse(data)
+            .is_some_and(|env| env.version == envelope::VERSION_AAD && env.key_id == self.active_id)
+    }
+
+    pub fn seal(&self, pin: &[u8], aad: &[u8]) -> Result<Vec<u8>, CipherError> {
+        let version = if aad.is_empty() { envelope::VERSION } else { envelope::VERSION_AAD };
+        let payload = seal(&self.keys[&self.active_id], pin, aad)?;
+        Ok(envelope::wrap(version, &self.active_id, &payload))
+    }
+
+    /// Unbound ciphertexts (version 1 or legacy) still open whatever `aad` is
+    /// passed, so records written before AAD existed stay readable until rebound.
+    /// With `require_aad` set they are refused whenever the caller passes AAD.
+    pub fn open(&self, data: &[u8], aad: &[u8]) -> Result<SecretBytes, CipherError> {
+        let unbound_allowed = !self.require_aad || aad.is_empty();
+        match envelope::parse(data) {
+            Some(env) => match self.keys.get(env.key_id) {
+                Some(cipher) if env.version == envelope::VERSION_AAD => open(cipher, env.payload, aad),
+                Some(_) if !unbound_allowed => Err(CipherError::Unbound),
+                Some(cipher) => open(cipher, env.payload, &[]),
+                None if !unbound_allowed => Err(CipherError::UnknownKey(env.key_id.to_string())),
+                // A legacy blob may look like an envelope by chance, so give it a go
+                // before reporting the key as unknown.
+                None => self
+                    .open_legacy(data)
+                    .map_err(|_| CipherError::UnknownKey(env.key_id.to_string())),
+            },
+            None if !unbound_allowed => Err(CipherError::Unbound),
+            None => self.open_legacy(data),
+        }
+    }
+
+    // Ciphertexts without a key ID are tried against every key in the ring.
+    fn open_legacy(&self, data: &[u8]) -> Result<SecretBytes, CipherError> {
+        let mut last_err = CipherError::Authentication;
+        for cipher in self.keys.values() {
+            match open(cipher, data, &[]) {
+                Ok(plaintext) => return Ok(plaintext),
+                Err(e) => last_err = e,
+            }
+        }
+        Err(last_err)
+    }
+}
+
+fn init<A: KeyInit>(key_id: &str, key: &[u8]) -> Result<A, CipherError> {
+    if key_id.is_empty() || key_id.len() > envelope::MAX_KEY_ID_LEN {
+        return Err(CipherError::InvalidKey {
+            id: key_id.to_string(),
+            reason: format!("key id must be 1 to {} bytes", envelope::MAX_KEY_ID_LEN),
+        });
+    }
+    A::new_from_slice(key).map_err(|_| CipherError::InvalidKey {
+        id: key_id.to_string(),
+        reason: "wrong key length".into(),
+    })
+}
+
+fn seal<A: Aead + AeadCore>(cipher: &A, pin: &[u8], aad: &[u8]) -> Result<Vec<u8>, CipherError> {
+    let nonce = utils::generate_nonce::<A>();
+    let mut ciphertext = cipher
+        .encrypt(&nonce, Payload { msg: pin, aad })
+        .map_err(|_| CipherError::Encryption)?;
+    let mut result = nonce.to_vec();
+    result.append(&mut ciphertext);
+    Ok(result)
+}
+
+fn open<A: Aead + AeadCore>(cipher: &A, data: &[u8], aad: &[u8]) -> Result<SecretBytes, CipherError> {
+    let nonce_len = A::NonceSize::USIZE;
+    let min = nonce_len + A::TagSize::USIZE;
+    if data.len() < min {
+        return Err(CipherError::ShortInput { len: data.len(), min });
+    }
+    // Separate nonce and ciphertext
+    let (nonce_bytes, ciphertext) = data.split_at(nonce_len);
+
+    let nonce = Nonce::<A>::from_slice(nonce_bytes);
+    cipher
+        .decrypt(nonce, Payload { msg: ciphertext, aad }) // pass nonce by reference
+        .map(SecretBytes::new)
+        .map_err(|_| CipherError::Authentication)
+}
+
+#[cfg(test)]
+mod tests {
+    use aes_gcm::Aes256Gcm;
+
+    use super::*;
+    use crate::application::env::{KeyConf, SecretSource};
+
+    fn keyring(require_aad: bool) -> Keyring<Aes256Gcm> {
+        let key = format!("hex:{}", hex::encode((0u8..32).collect::<Vec<_>>()));
+        Keyring::new(&KeyringConf {
+            active: KeyConf {
+                id: "k1".into(),
+                key: Some(SecretSource::Loaded(key.into())),
+                passphrase: None,
+                salt: None,
+                kdf: Default::default(),
+            },
+            retired: Vec::new(),
+            require_aad,
+        })
+        .unwrap()
+    }
+
+    #[test]
+    fn bound_ciphertexts_only_open_under_their_aad() {
+        let ring = keyring(false);
+        let sealed = ring.seal(b"1234", b"pincode:a").unwrap();
+        assert!(envelope::is_bound(&sealed));
+        assert_eq!(ring.open(&sealed, b"pincode:a").unwrap().expose(), b"1234");
+        assert!(matches!(ring.open(&sealed, b"pincode:b"), Err(CipherError::Authentication)));
+    }
+
+    #[test]
+    fn unbound_ciphertexts_are_refused_once_aad_is_required() {
+        let unbound = keyring(false).seal(b"1234", &[]).unwrap();
+        assert!(!envelope::is_bound(&unbound));
+        assert_eq!(keyring(false).open(&unbound, b"pincode:a").unwrap().expose(), b"1234");
+
+        let strict = keyring(true);
+        assert!(matches!(strict.open(&unbound, b"pincode:a"), Err(CipherError::Unbound)));
+        // Supplier files carry no AAD and still open
+        assert_eq!(strict.open(&unbound, &[]).unwrap().expose(), b"1234");
+        // A bare legacy ciphertext is refused as well
+        let legacy = &unbound[2 + "k1".len()..];
+        assert!(matches!(strict.open(legacy, b"pincode:a"), Err(CipherError::Unbound)));
+        assert_eq!(keyring(false).open(legacy, b"pincode:a").unwrap().expose(), b"1234");
+    }
+}
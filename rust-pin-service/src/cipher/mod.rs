@@ -1,26 +1,98 @@
 mod utils;
 pub mod aes;
-use serde::Deserialize;
+pub mod blind_index;
+pub mod chacha;
+pub mod envelope;
+pub mod error;
+pub mod kdf;
+pub mod keyring;
+pub mod secret;
+pub mod selftest;
+pub mod shamir;
+use std::sync::Arc;
 
-#[derive(Clone, Debug, Deserialize)]
+use base64::{engine::general_purpose, Engine as _};
+use serde::{Deserialize, Serialize};
+
+use crate::cipher::aes::{Aes128Cipher, Aes256Cipher, Aes256GcmSivCipher};
+use crate::cipher::chacha::{ChaCha20Cipher, XChaCha20Cipher};
+
+pub use error::CipherError;
+pub use secret::{SecretBytes, SecretString};
+
+#[derive(Clone, Debug, Deserialize, Serialize)]
 #[serde(rename_all = "lowercase")] 
 pub enum Algorithm{
     #[serde(rename = "aes128")]
     Aes128Gcm,
     #[serde(rename = "aes256")]
-    Aes256Gcm
+    Aes256Gcm,
+    #[serde(rename = "aes256-gcm-siv")]
+    Aes256GcmSiv,
+    #[serde(rename = "chacha20")]
+    ChaCha20Poly1305,
+    #[serde(rename = "xchacha20")]
+    XChaCha20Poly1305,
+}
+
+impl Algorithm {
+    pub fn key_len(&self) -> usize {
+        match self {
+            Algorithm::Aes128Gcm => 16,
+            _ => 32,
+        }
+    }
+}
+
+/// Builds a cipher around one raw key, e.g. an unwrapped data key.
+pub fn from_key(alg: &Algorithm, key_id: &str, key: &[u8]) -> Result<Arc<dyn Cipher + Send + Sync>, CipherError> {
+    let cipher: Arc<dyn Cipher + Send + Sync> = match alg {
+        Algorithm::Aes128Gcm => Arc::new(Aes128Cipher::from_key(key_id, key)?),
+        Algorithm::Aes256Gcm => Arc::new(Aes256Cipher::from_key(key_id, key)?),
+        Algorithm::Aes256GcmSiv => Arc::new(Aes256GcmSivCipher::from_key(key_id, key)?),
+        Algorithm::ChaCha20Poly1305 => Arc::new(ChaCha20Cipher::from_key(key_id, key)?),
+        Algorithm::XChaCha20Poly1305 => Arc::new(XChaCha20Cipher::from_key(key_id, key)?),
+    };
+    Ok(cipher)
 }
 
 pub trait Cipher {
     fn clone_box(&self) -> Box<dyn Cipher>;
-    fn encrypt(&self, pin: &[u8]) -> Vec<u8>;
-    fn decrypt(&self, data: &[u8]) -> Vec<u8>;
-    fn enc_encrypt(&self, data: String) -> String;
-    fn enc_decrypt(&self, data: String) -> String;
+    fn encrypt_with_aad(&self, pin: &[u8], aad: &[u8]) -> Result<Vec<u8>, CipherError>;
+    fn decrypt_with_aad(&self, data: &[u8], aad: &[u8]) -> Result<SecretBytes, CipherError>;
+    fn is_current(&self, data: &[u8]) -> bool;
+
+    fn encrypt(&self, pin: &[u8]) -> Result<Vec<u8>, CipherError> {
+        self.encrypt_with_aad(pin, &[])
+    }
+
+    fn decrypt(&self, data: &[u8]) -> Result<SecretBytes, CipherError> {
+        self.decrypt_with_aad(data, &[])
+    }
+
+    fn enc_encrypt(&self, pin: &str) -> Result<String, CipherError> {
+        self.enc_encrypt_with_aad(pin, &[])
+    }
+
+    fn enc_decrypt(&self, data: String) -> Result<SecretString, CipherError> {
+        self.enc_decrypt_with_aad(data, &[])
+    }
+
+    fn enc_encrypt_with_aad(&self, pin: &str, aad: &[u8]) -> Result<String, CipherError> {
+        let encrypted_pin = self.encrypt_with_aad(pin.as_bytes(), aad)?;
+        Ok(general_purpose::STANDARD.encode(encrypted_pin))
+    }
+
+    fn enc_decrypt_with_aad(&self, data: String, aad: &[u8]) -> Result<SecretString, CipherError> {
+        let decoded_data = general_purpose::STANDARD.decode(&data)?;
+        let decrypted_bytes = self.decrypt_with_aad(&decoded_data, aad)?;
+        // Convert decrypted bytes back to String (assuming UTF-8)
+        Ok(str::from_utf8(decrypted_bytes.expose())?.to_string().into())
+    }
 }
 
 impl Clone for Box<dyn Cipher> {
     fn clone(&self) -> Box<dyn Cipher> {
         self.clone_box()
     }
-}
\ No newline at end of file
+}
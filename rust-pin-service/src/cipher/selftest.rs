@@ -0,0 +1,154 @@
+use aes_gcm::{Aes128Gcm, Aes256Gcm};
+use aes_gcm::aead::{Aead, AeadCore, KeyInit, Nonce, Payload};
+use aes_gcm_siv::Aes256GcmSiv;
+use chacha20poly1305::{ChaCha20Poly1305, XChaCha20Poly1305};
+
+use super::{envelope, Algorithm, Cipher, CipherError};
+
+const KAT_KEY_ID: &str = "kat";
+const PROBE: &[u8] = b"0000000000000000";
+const PROBE_AAD: &[u8] = b"selftest:probe";
+
+// All fields hex encoded; `ciphertext` includes the tag
+struct Vector {
+    key: &'static str,
+    nonce: &'static str,
+    aad: &'static str,
+    plaintext: &'static str,
+    ciphertext: &'static str,
+}
+
+// NIST CAVS gcmEncryptExtIV128.rsp
+const AES_128_GCM: Vector = Vector {
+    key: "c939cc13397c1d37de6ae0e1cb7c423c",
+    nonce: "b3d8cc017cbb89b39e0f67e2",
+    aad: "24825602bd12a984e0092d3e448eda5f",
+    plaintext: "c3b3c41f113a31b73d9a5cd432103069",
+    ciphertext: "93fe7d9e9bfd10348a5606e5cafa73540032a1dc85f1c9786925a2e71d8272dd",
+};
+
+// NIST CAVS gcmEncryptExtIV256.rsp
+const AES_256_GCM: Vector = Vector {
+    key: "92e11dcdaa866f5ce790fd24501f92509aacf4cb8b1339d50c9c1240935dd08b",
+    nonce: "ac93a1a6145299bde902f21a",
+    aad: "1e0889016f67601c8ebea4943bc23ad6",
+    plaintext: "2d71bcfa914e4ac045b2aa60955fad24",
+    ciphertext: "8995ae2e6df3dbf96fac7b7137bae67feca5aa77d51d4a0a14d9c51e1da474ab",
+};
+
+// RFC 8452, appendix C.2
+const AES_256_GCM_SIV: Vector = Vector {
+    key: "0100000000000000000000000000000000000000000000000000000000000000",
+    nonce: "030000000000000000000000",
+    aad: "01",
+    plaintext: "0200000000000000",
+    ciphertext: "1de22967237a813291213f267e3b452f02d01ae33e4ec854",
+};
+
+// "Ladies and Gentlemen of the class of '99: ..." shared by both ChaCha vectors
+const CHACHA_PLAINTEXT: &str = "4c616469657320616e642047656e746c656d656e206f662074686520636c617373206f66202739393a204966204920636f756c64206f6666657220796f75206f6e6c79206f6e652074697020666f7220746865206675747572652c2073756e73637265656e20776f756c642062652069742e";
+
+// RFC 8439, section 2.8.2
+const CHACHA20_POLY1305: Vector = Vector {
+    key: "808182838485868788898a8b8c8d8e8f909192939495969798999a9b9c9d9e9f",
+    nonce: "070000004041424344454647",
+    aad: "50515253c0c1c2c3c4c5c6c7",
+    plaintext: CHACHA_PLAINTEXT,
+    ciphertext: "d31a8d34648e60db7b86afbc53ef7ec2a4aded51296e08fea9e2b5a736ee62d63dbea45e8ca9671282fafb69da92728b1a71de0a9e060b2905d6a5b67ecd3b3692ddbd7f2d778b8c9803aee328091b58fab324e4fad675945585808b4831d7bc3ff4def08e4b7a9de576d26586cec64b61161ae10b594f09e26a7e902ecbd0600691",
+};
+
+// draft-irtf-cfrg-xchacha, appendix A.3.1
+const XCHACHA20_POLY1305: Vector = Vector {
+    key: "808182838485868788898a8b8c8d8e8f909192939495969798999a9b9c9d9e9f",
+    nonce: "404142434445464748494a4b4c4d4e4f5051525354555657",
+    aad: "50515253c0c1c2c3c4c5c6c7",
+    plaintext: CHACHA_PLAINTEXT,
+    ciphertext: "bd6d179d3e83d43b9576579493c0e939572a1700252bfaccbed2902c21396cbb731c7f1b0b4aa6440bf3a82f4eda7e39ae64c6708c54c216cb96b72e1213b4522f8c9ba40db5d945b11b69b982c1bb9e3f3fac2bc369488f76b2383565d3fff921f9664c97637da9768812f615c68b13b52ec0875924c1c7987947deafd8780acf49",
+};
+
+/// Checks the AEAD backend for `alg` against a published test vector, then makes
+/// sure the configured `cipher` round-trips and rejects tampered or rebound input.
+pub fn run(alg: &Algorithm, cipher: &dyn Cipher) -> Result<(), CipherError> {
+    match alg {
+        Algorithm::Aes128Gcm => known_answer::<Aes128Gcm>(alg, &AES_128_GCM),
+        Algorithm::Aes256Gcm => known_answer::<Aes256Gcm>(alg, &AES_256_GCM),
+        Algorithm::Aes256GcmSiv => known_answer::<Aes256GcmSiv>(alg, &AES_256_GCM_SIV),
+        Algorithm::ChaCha20Poly1305 => known_answer::<ChaCha20Poly1305>(alg, &CHACHA20_POLY1305),
+        Algorithm::XChaCha20Poly1305 => known_answer::<XChaCha20Poly1305>(alg, &XCHACHA20_POLY1305),
+    }?;
+    round_trip(cipher)
+}
+
+fn known_answer<A: Aead + AeadCore + KeyInit>(alg: &Algorithm, vector: &Vector) -> Result<(), CipherError> {
+    let failed = |what: &str| CipherError::SelfTest(format!("{:?} {}", alg, what));
+    let [key, nonce, aad, plaintext, expected] =
+        [vector.key, vector.nonce, vector.aad, vector.plaintext, vector.ciphertext]
+            .map(|field| hex::decode(field).expect("test vectors are valid hex"));
+
+    let aead = A::new_from_slice(&key).map_err(|_| failed("rejected the test key"))?;
+    let ciphertext = aead
+        .encrypt(Nonce::<A>::from_slice(&nonce), Payload { msg: &plaintext, aad: &aad })
+        .map_err(|_| failed("failed to encrypt the test vector"))?;
+    if ciphertext != expected {
+        return Err(failed("does not match the test vector ciphertext"));
+    }
+
+    // Same vector through the envelope and keyring path stored PINs take
+    let sealed = envelope::wrap(envelope::VERSION_AAD, KAT_KEY_ID, &[nonce, ciphertext].concat());
+    let opened = super::from_key(alg, KAT_KEY_ID, &key)?
+        .decrypt_with_aad(&sealed, &aad)
+        .map_err(|e| failed(&format!("failed to open the test vector: {}", e)))?;
+    if *opened.expose() != plaintext {
+        return Err(failed("opened the test vector to the wrong plaintext"));
+    }
+    Ok(())
+}
+
+fn round_trip(cipher: &dyn Cipher) -> Result<(), CipherError> {
+    let failed = |what: &str| CipherError::SelfTest(format!("configured cipher {}", what));
+
+    let sealed = cipher.encrypt_with_aad(PROBE, PROBE_AAD)?;
+    if !cipher.is_current(&sealed) {
+        return Err(failed("does not seal under the active key"));
+    }
+    let opened = cipher
+        .decrypt_with_aad(&sealed, PROBE_AAD)
+        .map_err(|e| failed(&format!("cannot open its own ciphertext: {}", e)))?;
+    if opened.expose() != PROBE {
+        return Err(failed("round-trips to the wrong plaintext"));
+    }
+
+    let mut tampered = sealed.clone();
+    if let Some(last) = tampered.last_mut() {
+        *last ^= 1;
+    }
+    if cipher.decrypt_with_aad(&tampered, PROBE_AAD).is_ok() {
+        return Err(failed("accepted a tampered ciphertext"));
+    }
+    if cipher.decrypt_with_aad(&sealed, b"selftest:other").is_ok() {
+        return Err(failed("accepted a ciphertext under the wrong associated data"));
+    }
+    Ok(())
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn every_backend_passes_its_known_answer_test() {
+        for alg in [
+            Algorithm::Aes128Gcm,
+            Algorithm::Aes256Gcm,
+            Algorithm::Aes256GcmSiv,
+            Algorithm::ChaCha20Poly1305,
+            Algorithm::XChaCha20Poly1305,
+        ] {
+            let key = vec![7u8; alg.key_len()];
+            let cipher = crate::cipher::from_key(&alg, "test", &key).unwrap();
+            if let Err(e) = run(&alg, cipher.as_ref()) {
+                panic!("{:?}: {}", alg, e);
+            }
+        }
+    }
+}
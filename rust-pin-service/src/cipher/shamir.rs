@@ -0,0 +1,138 @@
+use sha2::{Digest, Sha256};
+use sharks::{Share, Sharks};
+
+use super::{CipherError, SecretBytes, SecretString};
+
+const CHECKSUM_LEN: usize = 4;
+
+/// Splits `key` into `shares` hex encoded shares, any `threshold` of which rebuild it.
+///
+/// A short checksum is split along with the key, so a wrong or mixed set of shares
+/// is rejected by `combine` instead of producing a bogus key.
+pub fn split(key: &SecretBytes, threshold: u8, shares: u8) -> Result<Vec<SecretString>, CipherError> {
+    if threshold < 2 || threshold > shares {
+        return Err(CipherError::InvalidShare(format!(
+            "threshold must be between 2 and the number of shares ({})",
+            shares
+        )));
+    }
+    let secret = SecretBytes::new([key.expose().as_slice(), &checksum(key.expose())].concat());
+    Ok(Sharks(threshold)
+        .dealer(secret.expose())
+        .take(shares as usize)
+        .map(|share| {
+            let bytes = SecretBytes::new(Vec::from(&share));
+            SecretString::new(hex::encode(bytes.expose()))
+        })
+        .collect())
+}
+
+pub fn decode_share(share: &str) -> Result<SecretBytes, CipherError> {
+    let invalid = |reason: &str| CipherError::InvalidShare(reason.into());
+    let bytes = SecretBytes::new(hex::decode(share.trim()).map_err(|_| invalid("share is not valid hex"))?);
+    match bytes.expose().split_first() {
+        Some((_, y)) if y.len() <= CHECKSUM_LEN => Err(invalid("share is too short")),
+        // x = 0 would be the secret itself, which `split` never hands out
+        Some((0, _)) => Err(invalid("share has an invalid index")),
+        Some(_) => Ok(bytes),
+        None => Err(invalid("share is empty")),
+    }
+}
+
+/// Hex SHA-256 of a decoded share, published by `keygen` so a vault can check each
+/// share on arrival without holding any of them.
+pub fn share_digest(share: &SecretBytes) -> String {
+    hex::encode(Sha256::digest(share.expose()))
+}
+
+/// Index of a decoded share; two shares with the same index add nothing.
+pub fn share_index(share: &SecretBytes) -> u8 {
+    share.expose()[0]
+}
+
+pub fn combine(shares: &[SecretBytes], threshold: u8) -> Result<SecretBytes, CipherError> {
+    let invalid = |reason: &str| CipherError::InvalidShare(reason.into());
+    let shares = shares
+        .iter()
+        .map(|share| Share::try_from(share.expose().as_slice()))
+        .collect::<Result<Vec<_>, _>>()
+        .map_err(invalid)?;
+    let secret = SecretBytes::new(Sharks(threshold).recover(&shares).map_err(invalid)?);
+
+    let (key, sum) = secret.expose().split_at(secret.expose().len() - CHECKSUM_LEN);
+    if checksum(key) != sum {
+        return Err(invalid("shares do not combine to a valid key"));
+    }
+    Ok(SecretBytes::new(key.to_vec()))
+}
+
+fn checksum(key: &[u8]) -> [u8; CHECKSUM_LEN] {
+    let digest = Sha256::digest(key);
+    let mut sum = [0u8; CHECKSUM_LEN];
+    sum.copy_from_slice(&digest[..CHECKSUM_LEN]);
+    sum
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn key() -> SecretBytes {
+        SecretBytes::new((1u8..=32).collect())
+    }
+
+    fn decoded(shares: &[SecretString]) -> Vec<SecretBytes> {
+        shares.iter().map(|share| decode_share(share.expose()).unwrap()).collect()
+    }
+
+    #[test]
+    fn any_threshold_shares_rebuild_the_key() {
+        let shares = decoded(&split(&key(), 3, 5).unwrap());
+        for subset in [&shares[..3], &shares[2..], &[shares[0].clone(), shares[2].clone(), shares[4].clone()]] {
+            assert_eq!(combine(subset, 3).unwrap().expose(), key().expose());
+        }
+    }
+
+    #[test]
+    fn fewer_than_threshold_shares_do_not_rebuild_the_key() {
+        let shares = decoded(&split(&key(), 3, 5).unwrap());
+        assert!(combine(&shares[..2], 3).is_err());
+    }
+
+    #[test]
+    fn shares_of_different_keys_fail_the_checksum() {
+        let other = SecretBytes::new((101u8..=132).collect());
+        let mut shares = decoded(&split(&key(), 2, 3).unwrap());
+        shares.truncate(1);
+        shares.push(decoded(&split(&other, 2, 3).unwrap()).swap_remove(1));
+
+        match combine(&shares, 2) {
+            Err(CipherError::InvalidShare(reason)) => assert_eq!(reason, "shares do not combine to a valid key"),
+            Err(e) => panic!("unexpected error: {}", e),
+            Ok(_) => panic!("mixed shares rebuilt a key"),
+        }
+    }
+
+    #[test]
+    fn split_rejects_thresholds_out_of_range() {
+        assert!(split(&key(), 1, 3).is_err());
+        assert!(split(&key(), 4, 3).is_err());
+    }
+
+    #[test]
+    fn decode_share_rejects_malformed_input() {
+        assert!(decode_share("not hex").is_err());
+        assert!(decode_share("").is_err());
+        assert!(decode_share("0101020304").is_err());
+        assert!(decode_share(&format!("00{}", "ab".repeat(36))).is_err());
+    }
+
+    #[test]
+    fn share_digest_identifies_a_share() {
+        let shares = decoded(&split(&key(), 2, 3).unwrap());
+        let digests: Vec<_> = shares.iter().map(share_digest).collect();
+        assert_eq!(digests[0], share_digest(&shares[0]));
+        assert_ne!(digests[0], digests[1]);
+        assert_eq!(digests[0].len(), 64);
+    }
+}
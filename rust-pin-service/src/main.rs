@@ -1,5 +1,5 @@
 
-use crate::application::boot;
+use crate::application::{boot, cli};
 
 
 pub mod application;
@@ -13,7 +13,14 @@ pub mod vault {
 
 #[tokio::main(flavor = "multi_thread", worker_threads = 10)]
 async fn main() -> Result<(), Box<dyn std::error::Error>> {
-    let _ = boot().await;
+    let args: Vec<String> = std::env::args().skip(1).collect();
+    match args.first().map(String::as_str) {
+        Some("keygen") => return cli::keygen(&args[1..]),
+        Some("unseal") => return cli::unseal(&args[1..]).await,
+        _ => {}
+    }
+
+    boot().await?;
     
     tokio::signal::ctrl_c().await?;
     println!("Shutting down...");
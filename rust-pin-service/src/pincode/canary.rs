@@ -0,0 +1,62 @@
+use base64::{engine::general_purpose, Engine as _};
+use bson::DateTime;
+
+use crate::application::AppContext;
+use crate::cipher::{CipherError, SecretString};
+use crate::pincode::datakey::DataKeyError;
+use crate::pincode::model::VaultCanary;
+use crate::pincode::model::repository::{PinCodeRepository, VaultCanaryRepository};
+
+pub const CANARY_ID: &str = "canary";
+const CANARY_VALUE: &str = "topup-cipher-vault";
+const CANARY_AAD: &[u8] = b"vault-meta:canary";
+
+/// Refuses to go on when the configured keys cannot open the canary, or, on the
+/// first boot after upgrading, the oldest stored PIN. A canary sealed under a
+/// retired key is resealed so it survives that key being dropped later.
+pub async fn verify(context: &AppContext) -> Result<(), DataKeyError> {
+    let cipher = context.cipher.as_ref().ok_or(CipherError::NotInitialized)?;
+    let canary_repo = VaultCanaryRepository::new(context);
+    let mismatch = |e: DataKeyError| match e {
+        DataKeyError::Cipher(e) => DataKeyError::Cipher(CipherError::SelfTest(format!(
+            "configured keys do not match the vault contents: {}",
+            e
+        ))),
+        e => e,
+    };
+
+    let Some(canary) = canary_repo.find_by_id(CANARY_ID).await? else {
+        let pincode_repo = PinCodeRepository::new(context);
+        if let Some(pin_code) = pincode_repo.find_page(None, 1).await?.first() {
+            pincode_repo.open(pin_code).await.map_err(mismatch)?;
+        }
+
+        let now = DateTime::now();
+        canary_repo
+            .insert_one(&VaultCanary {
+                id: CANARY_ID.to_string(),
+                encrypted: cipher.enc_encrypt_with_aad(CANARY_VALUE, CANARY_AAD)?,
+                created_at: now,
+                updated_at: now,
+            })
+            .await?;
+        println!("Vault canary created");
+        return Ok(());
+    };
+
+    let value: SecretString = cipher
+        .enc_decrypt_with_aad(canary.encrypted.clone(), CANARY_AAD)
+        .map_err(|e| mismatch(e.into()))?;
+    if value.expose() != CANARY_VALUE {
+        return Err(mismatch(CipherError::Authentication.into()));
+    }
+
+    let data = general_purpose::STANDARD.decode(&canary.encrypted)?;
+    if !cipher.is_current(&data) {
+        let encrypted = cipher.enc_encrypt_with_aad(CANARY_VALUE, CANARY_AAD)?;
+        canary_repo.replace_encrypted(CANARY_ID, &canary.encrypted, &encrypted).await?;
+        println!("Vault canary resealed under the active key");
+    }
+    println!("Vault canary verified");
+    Ok(())
+}
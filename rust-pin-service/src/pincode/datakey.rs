@@ -0,0 +1,171 @@
+use std::collections::HashMap;
+use std::fmt;
+use std::sync::{Arc, RwLock};
+
+use base64::{engine::general_purpose, Engine as _};
+use bson::{DateTime, oid::ObjectId};
+use rand::RngCore;
+use tonic::Status;
+
+use crate::application::AppContext;
+use crate::cipher::{self, Algorithm, Cipher, CipherError, SecretBytes};
+use crate::pincode::model::DataKey;
+use crate::pincode::model::repository::DataKeyRepository;
+
+type SharedCipher = Arc<dyn Cipher + Send + Sync>;
+
+#[derive(Debug)]
+pub enum DataKeyError {
+    Cipher(CipherError),
+    Database(mongodb::error::Error),
+    NotFound(ObjectId),
+}
+
+impl fmt::Display for DataKeyError {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            DataKeyError::Cipher(e) => write!(f, "{}", e),
+            DataKeyError::Database(e) => write!(f, "data key storage failed: {}", e),
+            DataKeyError::NotFound(id) => write!(f, "data key {} not found", id),
+        }
+    }
+}
+
+impl std::error::Error for DataKeyError {}
+
+impl From<CipherError> for DataKeyError {
+    fn from(e: CipherError) -> Self {
+        DataKeyError::Cipher(e)
+    }
+}
+
+impl From<base64::DecodeError> for DataKeyError {
+    fn from(e: base64::DecodeError) -> Self {
+        DataKeyError::Cipher(CipherError::Base64(e))
+    }
+}
+
+impl From<mongodb::error::Error> for DataKeyError {
+    fn from(e: mongodb::error::Error) -> Self {
+        DataKeyError::Database(e)
+    }
+}
+
+impl From<DataKeyError> for Status {
+    fn from(e: DataKeyError) -> Self {
+        match e {
+            DataKeyError::Cipher(e) => e.into(),
+            _ => Status::internal(e.to_string()),
+        }
+    }
+}
+
+pub struct RewrapReport {
+    pub rewrapped: i64,
+    pub skipped: i64,
+    pub failed: i64,
+}
+
+/// Envelope encryption: every upload or generation batch gets its own random
+/// data key, and only the wrapped form of that key is persisted. Rotating the
+/// master key then means rewrapping `data-keys`, not touching `pincodes`.
+#[derive(Clone)]
+pub struct DataKeyStore {
+    repo: DataKeyRepository,
+    master: Option<SharedCipher>,
+    alg: Algorithm,
+    cache: Arc<RwLock<HashMap<ObjectId, SharedCipher>>>,
+}
+
+impl DataKeyStore {
+    pub fn new(context: &AppContext) -> Self {
+        Self {
+            repo: DataKeyRepository::new(context),
+            master: context.cipher.clone(),
+            alg: context.env.cipher.alg.clone(),
+            cache: Arc::new(RwLock::new(HashMap::new())),
+        }
+    }
+
+    fn master(&self) -> Result<&SharedCipher, CipherError> {
+        self.master.as_ref().ok_or(CipherError::NotInitialized)
+    }
+
+    pub async fn create(&self) -> Result<ObjectId, DataKeyError> {
+        let id = ObjectId::new();
+        let mut key = SecretBytes::new(vec![0u8; self.alg.key_len()]);
+        rand::thread_rng().fill_bytes(key.expose_mut());
+
+        let wrapped = self.master()?.encrypt_with_aad(key.expose(), &aad(id))?;
+        let cipher = cipher::from_key(&self.alg, &id.to_hex(), key.expose())?;
+
+        self.repo
+            .insert_one(&DataKey {
+                id,
+                wrapped: general_purpose::STANDARD.encode(wrapped),
+                alg: self.alg.clone(),
+                created_at: DateTime::now(),
+                rewrapped_at: None,
+            })
+            .await?;
+        self.cache.write().unwrap().insert(id, cipher);
+        Ok(id)
+    }
+
+    /// Cipher for a PIN's data key, or the master cipher for PINs without one.
+    pub async fn cipher_for(&self, data_key_id: Option<ObjectId>) -> Result<SharedCipher, DataKeyError> {
+        let Some(id) = data_key_id else {
+            return Ok(self.master()?.clone());
+        };
+        if let Some(cipher) = self.cache.read().unwrap().get(&id) {
+            return Ok(cipher.clone());
+        }
+
+        let data_key = self.repo.find_by_id(id).await?.ok_or(DataKeyError::NotFound(id))?;
+        let wrapped = general_purpose::STANDARD.decode(&data_key.wrapped)?;
+        let key = self.master()?.decrypt_with_aad(&wrapped, &aad(id))?;
+        let cipher = cipher::from_key(&data_key.alg, &id.to_hex(), key.expose())?;
+
+        self.cache.write().unwrap().insert(id, cipher.clone());
+        Ok(cipher)
+    }
+
+    /// Reseals every data key that is not wrapped under the active master key.
+    pub async fn rewrap_all(&self) -> Result<RewrapReport, DataKeyError> {
+        let master = self.master()?;
+        let mut report = RewrapReport { rewrapped: 0, skipped: 0, failed: 0 };
+
+        for data_key in self.repo.find_all().await? {
+            match rewrap(master.as_ref(), &data_key) {
+                Ok(None) => report.skipped += 1,
+                Ok(Some(wrapped)) => {
+                    let now = DateTime::now();
+                    if self.repo.replace_wrapped(data_key.id, &data_key.wrapped, &wrapped, now).await? {
+                        report.rewrapped += 1;
+                    } else {
+                        report.skipped += 1;
+                    }
+                }
+                Err(e) => {
+                    println!("Rewrapping data key {} failed: {}", data_key.id, e);
+                    report.failed += 1;
+                }
+            }
+        }
+        Ok(report)
+    }
+}
+
+fn aad(id: ObjectId) -> Vec<u8> {
+    format!("datakey:{}", id.to_hex()).into_bytes()
+}
+
+fn rewrap(master: &(dyn Cipher + Send + Sync), data_key: &DataKey) -> Result<Option<String>, CipherError> {
+    let wrapped = general_purpose::STANDARD.decode(&data_key.wrapped)?;
+    if master.is_current(&wrapped) {
+        return Ok(None);
+    }
+    let key = master.decrypt_with_aad(&wrapped, &aad(data_key.id))?;
+    let rewrapped = master.encrypt_with_aad(key.expose(), &aad(data_key.id))?;
+    Ok(Some(general_purpose::STANDARD.encode(rewrapped)))
+}
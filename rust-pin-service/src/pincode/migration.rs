@@ -0,0 +1,51 @@
+use mongodb::error::Error;
+
+use crate::pincode::model::repository::PinCodeRepository;
+
+pub const DEFAULT_BATCH_SIZE: i64 = 500;
+
+pub struct StripReport {
+    pub stripped: i64,
+    pub failed: i64,
+}
+
+/// One-off cleanup for documents that still hold the plaintext PIN. The field is
+/// only removed once `encrypted` has been shown to decrypt back to the same value;
+/// anything else is left in place and counted as failed.
+pub async fn strip_plaintext_pins(
+    pincode_repo: PinCodeRepository,
+    batch_size: i64,
+) -> Result<StripReport, Error> {
+    let mut report = StripReport { stripped: 0, failed: 0 };
+    let mut last_id = None;
+
+    loop {
+        let page = pincode_repo.find_plaintext_page(last_id, batch_size).await?;
+        if page.is_empty() {
+            break;
+        }
+
+        for legacy in &page {
+            let Some(id) = legacy.pin_code.id else { continue };
+            match pincode_repo.open(&legacy.pin_code).await {
+                Ok(pin) if pin.expose() == legacy.pincode.expose() => {
+                    if pincode_repo.strip_plaintext(id, legacy.pincode.expose()).await? {
+                        report.stripped += 1;
+                    }
+                }
+                Ok(_) => {
+                    println!("Plaintext does not match ciphertext for {}", id);
+                    report.failed += 1;
+                }
+                Err(e) => {
+                    println!("Ciphertext check failed for {}: {}", id, e);
+                    report.failed += 1;
+                }
+            }
+        }
+
+        last_id = page.last().and_then(|legacy| legacy.pin_code.id);
+    }
+
+    Ok(report)
+}
@@ -1,4 +1,10 @@
 pub mod service;
 pub mod utils;
+pub mod canary;
+pub mod datakey;
+pub mod migration;
 pub mod model;
+pub mod reservation;
+pub mod rotation;
+pub mod sweeper;
  
\ No newline at end of file
@@ -2,6 +2,8 @@ use bson::{DateTime, oid::ObjectId};
 use serde::{Deserialize, Serialize};
 use std::fmt;
 
+use crate::cipher::{Algorithm, SecretString};
+
 pub mod repository;
 
 #[derive(Debug, Serialize, Deserialize)]
@@ -10,6 +12,11 @@ pub enum PinStatus {
     Active,
     Reserved,
     Purchased,
+    // Pulled by a batch recall; never handed out again
+    Voided,
+    // Past the operator's validity date; never handed out again. Reserved for when
+    // PINs carry such a date: nothing assigns it yet
+    Expired,
 }
 
 impl fmt::Display for PinStatus {
@@ -18,6 +25,8 @@ impl fmt::Display for PinStatus {
             PinStatus::Active => "Active",
             PinStatus::Reserved => "Reserved",
             PinStatus::Purchased => "Purchased",
+            PinStatus::Voided => "Voided",
+            PinStatus::Expired => "Expired",
         };
         write!(f, "{}", s)
     }
@@ -28,7 +37,6 @@ pub struct PinCode {
     #[serde(rename = "_id")]
     pub id: Option<ObjectId>,
 
-    pub pincode: String,
     pub encrypted: String,
     pub status: PinStatus,
 
@@ -46,14 +54,247 @@ pub struct PinCode {
 
     #[serde(rename = "expiresAt")]
     pub expires_at: Option<DateTime>,
+
+    // Unset for PINs sealed directly under the master key
+    #[serde(rename = "dataKeyId")]
+    pub data_key_id: Option<ObjectId>,
+
+    // HMAC of the plaintext, unique across the collection; unset on old records
+    #[serde(rename = "blindIndex")]
+    pub blind_index: Option<String>,
+
+    // Product the PIN is sold as; unset on records stored before products were tracked
+    pub operator: Option<String>,
+    pub denomination: Option<i64>,
+    pub currency: Option<String>,
+
+    // Upload or generation run the PIN came from; unset on records stored before batches
+    #[serde(rename = "batchId")]
+    pub batch_id: Option<ObjectId>,
+
+    #[serde(rename = "voidedAt")]
+    pub voided_at: Option<DateTime>,
+
+    // What `encrypted` is bound to, see `PinCode::aad`; unset on records bound to their ID only
+    #[serde(rename = "aadVersion")]
+    pub aad_version: Option<i32>,
+}
+
+/// AAD version every PIN is sealed under now: bound to its ID, product and batch.
+pub const AAD_VERSION: i32 = 2;
+
+/// What a PIN is sold as: an operator's voucher of a given face value.
+#[derive(Debug, Clone, PartialEq, Serialize, Deserialize)]
+pub struct Product {
+    pub operator: String,
+    // In minor units of `currency`
+    pub denomination: i64,
+    pub currency: String,
+}
+
+// Documents written before the plaintext column was dropped
+#[derive(Debug, Deserialize)]
+pub struct PlaintextPinCode {
+    pub pincode: SecretString,
+    #[serde(flatten)]
+    pub pin_code: PinCode,
+}
+
+impl PinCode {
+    /// Associated data the stored ciphertext is bound to, per its `aad_version`.
+    pub fn aad(&self) -> Vec<u8> {
+        self.aad_for(self.aad_version)
+    }
+
+    /// Associated data under `version`. Records without one are bound to their ID
+    /// only, so the ciphertext cannot be moved to another record; from version 2
+    /// the product and batch are bound too, so it cannot be relabelled either.
+    pub fn aad_for(&self, version: Option<i32>) -> Vec<u8> {
+        let id = self.id.map(|id| id.to_hex()).unwrap_or_default();
+        match version {
+            None | Some(1) => format!("pincode:{}", id).into_bytes(),
+            // JSON keeps the fields apart whatever characters they hold
+            Some(version) => serde_json::to_vec(&(
+                "pincode",
+                version,
+                id,
+                &self.operator,
+                self.denomination,
+                &self.currency,
+                self.batch_id.map(|id| id.to_hex()),
+            ))
+            .expect("AAD fields always serialize"),
+        }
+    }
 }
 
 #[derive(Debug, Serialize, Deserialize)]
 pub struct PinCodeReservation {
     #[serde(rename = "_id")]
     pub id: Option<ObjectId>,
+    // Only on reservations made before several PINs could be held at once
     #[serde(rename = "pincodeId")]
     pub pincode_id: Option<ObjectId>,
+    #[serde(rename = "pincodeIds", default)]
+    pub pincode_ids: Vec<ObjectId>,
     #[serde(rename = "reservedAt")]
     pub reserved_at: DateTime,
+    #[serde(rename = "purchasedAt")]
+    pub purchased_at: Option<DateTime>,
+    #[serde(rename = "releasedAt")]
+    pub released_at: Option<DateTime>,
+    // Set once the hold lapsed and the PIN went back to the pool
+    #[serde(rename = "expiredAt")]
+    pub expired_at: Option<DateTime>,
+    // Set when a batch recall voided the held PINs
+    #[serde(rename = "voidedAt")]
+    pub voided_at: Option<DateTime>,
+}
+
+impl PinCodeReservation {
+    /// Every PIN held by this reservation.
+    pub fn pin_ids(&self) -> Vec<ObjectId> {
+        match self.pincode_id {
+            Some(id) if self.pincode_ids.is_empty() => vec![id],
+            _ => self.pincode_ids.clone(),
+        }
+    }
+}
+
+#[derive(Debug, Serialize, Deserialize)]
+pub struct JobCheckpoint {
+    #[serde(rename = "_id")]
+    pub id: String,
+    #[serde(rename = "lastId")]
+    pub last_id: Option<ObjectId>,
+    pub migrated: i64,
+    pub skipped: i64,
+    pub failed: i64,
+    // Records this run left sealed without AAD because it could not rebind them
+    #[serde(default)]
+    pub unbound: i64,
+    pub completed: bool,
+    #[serde(rename = "startedAt")]
+    pub started_at: DateTime,
+    #[serde(rename = "updatedAt")]
+    pub updated_at: DateTime,
+}
+
+/// Per-batch data key, stored only wrapped (encrypted) under the master key.
+#[derive(Debug, Serialize, Deserialize)]
+pub struct DataKey {
+    #[serde(rename = "_id")]
+    pub id: ObjectId,
+    pub wrapped: String,
+    pub alg: Algorithm,
+    #[serde(rename = "createdAt")]
+    pub created_at: DateTime,
+    #[serde(rename = "rewrappedAt")]
+    pub rewrapped_at: Option<DateTime>,
+}
+
+#[derive(Debug, Clone, Serialize, Deserialize)]
+#[serde(rename_all = "PascalCase")]
+pub enum BatchSource {
+    Upload,
+    Generated,
+}
+
+impl fmt::Display for BatchSource {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        let s = match self {
+            BatchSource::Upload => "Upload",
+            BatchSource::Generated => "Generated",
+        };
+        write!(f, "{}", s)
+    }
+}
+
+/// One upload or generation run. Every PIN it stored carries its ID, so a bad
+/// supplier file can be traced and recalled.
+#[derive(Debug, Serialize, Deserialize)]
+pub struct Batch {
+    #[serde(rename = "_id")]
+    pub id: ObjectId,
+    pub source: BatchSource,
+    pub supplier: Option<String>,
+    #[serde(rename = "fileName")]
+    pub file_name: Option<String>,
+    // SHA-256 of the uploaded file, set once the upload completes
+    pub checksum: Option<String>,
+    // Taken from the caller's client certificate, never from the request
+    pub uploader: Option<String>,
+    #[serde(flatten)]
+    pub product: Product,
+    #[serde(rename = "dataKeyId")]
+    pub data_key_id: ObjectId,
+    // PINs stored; duplicates and failed inserts are not counted
+    pub count: i64,
+    #[serde(rename = "createdAt")]
+    pub created_at: DateTime,
+    #[serde(rename = "completedAt")]
+    pub completed_at: Option<DateTime>,
+    #[serde(rename = "recalledAt")]
+    pub recalled_at: Option<DateTime>,
+    #[serde(rename = "recallReason")]
+    pub recall_reason: Option<String>,
+    // Why the run stopped before every PIN was stored
+    pub error: Option<String>,
+}
+
+/// Small known value sealed under the master key on first boot, so a later boot
+/// can tell whether its keys still match what is already stored.
+#[derive(Debug, Serialize, Deserialize)]
+pub struct VaultCanary {
+    #[serde(rename = "_id")]
+    pub id: String,
+    pub encrypted: String,
+    #[serde(rename = "createdAt")]
+    pub created_at: DateTime,
+    #[serde(rename = "updatedAt")]
+    pub updated_at: DateTime,
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn pin_code(operator: &str, batch_id: ObjectId) -> PinCode {
+        PinCode {
+            id: Some(ObjectId::parse_str("65f000000000000000000001").unwrap()),
+            encrypted: String::new(),
+            status: PinStatus::Active,
+            created_at: None,
+            purchased_at: None,
+            reserved_at: None,
+            reservation_id: None,
+            expires_at: None,
+            data_key_id: None,
+            blind_index: None,
+            operator: Some(operator.into()),
+            denomination: Some(10_000),
+            currency: Some("IRR".into()),
+            batch_id: Some(batch_id),
+            voided_at: None,
+            aad_version: Some(AAD_VERSION),
+        }
+    }
+
+    #[test]
+    fn id_only_aad_reads_records_sealed_before_products() {
+        let batch = ObjectId::new();
+        let mut legacy = pin_code("MCI", batch);
+        legacy.aad_version = None;
+        assert_eq!(legacy.aad(), b"pincode:65f000000000000000000001");
+        assert_eq!(legacy.aad(), pin_code("MTN", batch).aad_for(Some(1)));
+    }
+
+    #[test]
+    fn current_aad_binds_product_and_batch() {
+        let batch = ObjectId::new();
+        let sealed = pin_code("MCI", batch).aad();
+        assert_ne!(sealed, pin_code("MCI", batch).aad_for(None));
+        assert_ne!(sealed, pin_code("MTN", batch).aad());
+        assert_ne!(sealed, pin_code("MCI", ObjectId::new()).aad());
+    }
 }
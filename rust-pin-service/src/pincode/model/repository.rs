@@ -1,131 +1,534 @@
-use std::io;
+use base64::{engine::general_purpose, Engine as _};
 
 use crate::{
     application::AppContext,
-    pincode::model::{PinCode, PinCodeReservation, PinStatus},
+    cipher::{SecretString, blind_index::BlindIndex, envelope},
+    pincode::datakey::{DataKeyError, DataKeyStore},
+    pincode::model::{
+        AAD_VERSION, Batch, DataKey, JobCheckpoint, PinCode, PinCodeReservation, PinStatus, PlaintextPinCode,
+        Product, VaultCanary,
+    },
+};
+use bson::{DateTime, Document, doc, oid::ObjectId, to_bson};
+use futures::TryStreamExt;
+use mongodb::{
+    ClientSession,
+    Collection,
+    error::Error,
+    IndexModel,
+    options::{FindOneAndUpdateOptions, FindOptions, IndexOptions, ReplaceOptions, ReturnDocument},
 };
-use bson::{DateTime, doc, oid::ObjectId, to_bson};
-use mongodb::{Collection, error::Error};
 
-#[derive(Debug, Clone)]
+#[derive(Clone)]
 pub struct PinCodeRepository {
     collection: Collection<PinCode>,
+    keys: DataKeyStore,
+    blind_index: BlindIndex,
 }
 
 impl PinCodeRepository {
     pub fn new(context: &AppContext) -> Self {
         Self {
             collection: context.db_client.db().collection("pincodes"),
+            keys: DataKeyStore::new(context),
+            blind_index: context.blind_index.clone(),
         }
     }
 
+    pub async fn ensure_indexes(&self) -> Result<(), Error> {
+        // Sparse, so records written before the blind index existed do not collide
+        let options = IndexOptions::builder().unique(true).sparse(true).build();
+        let index = IndexModel::builder()
+            .keys(doc! { "blindIndex": 1 })
+            .options(options)
+            .build();
+        self.collection.create_index(index, None).await?;
+        let options = IndexOptions::builder().sparse(true).build();
+        let index = IndexModel::builder()
+            .keys(doc! { "reservationId": 1 })
+            .options(options)
+            .build();
+        self.collection.create_index(index, None).await?;
+        // Batch status breakdowns and recalls
+        let index = IndexModel::builder()
+            .keys(doc! { "batchId": 1, "status": 1 })
+            .build();
+        self.collection.create_index(index, None).await?;
+        // Claims for a given product
+        let index = IndexModel::builder()
+            .keys(doc! { "operator": 1, "denomination": 1, "currency": 1, "status": 1, "expiresAt": 1 })
+            .build();
+        self.collection.create_index(index, None).await?;
+        // Serves both claiming a lapsed hold and the expiry sweep
+        let index = IndexModel::builder()
+            .keys(doc! { "status": 1, "expiresAt": 1 })
+            .build();
+        self.collection.create_index(index, None).await?;
+        Ok(())
+    }
+
+    pub fn blind_index(&self, pin: &SecretString) -> String {
+        self.blind_index.compute(pin.expose())
+    }
+
+    pub fn keys(&self) -> &DataKeyStore {
+        &self.keys
+    }
+
+    /// Encrypts `pin` bound to `pin_code`, whose id (and data key, if any) must
+    /// already be assigned.
+    pub async fn seal(&self, pin_code: &PinCode, pin: &SecretString) -> Result<String, DataKeyError> {
+        let cipher = self.keys.cipher_for(pin_code.data_key_id).await?;
+        // Always under the current version; callers store `AAD_VERSION` with the result
+        Ok(cipher.enc_encrypt_with_aad(pin.expose(), &pin_code.aad_for(Some(AAD_VERSION)))?)
+    }
+
+    pub async fn open(&self, pin_code: &PinCode) -> Result<SecretString, DataKeyError> {
+        let cipher = self.keys.cipher_for(pin_code.data_key_id).await?;
+        Ok(cipher.enc_decrypt_with_aad(pin_code.encrypted.clone(), &pin_code.aad())?)
+    }
+
+    // Sealed against the record's AAD, whichever key it is under
+    pub fn is_bound(&self, pin_code: &PinCode) -> bool {
+        general_purpose::STANDARD
+            .decode(&pin_code.encrypted)
+            .is_ok_and(|data| envelope::is_bound(&data))
+    }
+
+    pub async fn is_current(&self, pin_code: &PinCode) -> Result<bool, DataKeyError> {
+        let data = general_purpose::STANDARD.decode(&pin_code.encrypted)?;
+        let cipher = self.keys.cipher_for(pin_code.data_key_id).await?;
+        Ok(pin_code.aad_version == Some(AAD_VERSION) && cipher.is_current(&data))
+    }
+
     pub async fn find_by_id(&self, id: &str) -> Option<PinCode> {
         let object_id = ObjectId::parse_str(id).ok()?;
         let filter = doc! { "_id": object_id };
         self.collection.find_one(filter, None).await.ok().flatten()
     }
 
-    pub async fn find_available(&self) -> Option<PinCode> {
-        let now = DateTime::now();
+    /// Claims one available PIN of `product` (of any product when `None`) for
+    /// `reservation_id`: an active one, or a reserved one whose hold has lapsed.
+    /// Voided, expired and sold PINs never match. Matching and updating happen in
+    /// one atomic operation, so concurrent callers can never claim the same PIN.
+    /// Returns the PIN as it was before the claim, so a lapsed reservation it took
+    /// over can be closed.
+    pub async fn claim_available(
+        &self,
+        reservation_id: ObjectId,
+        product: Option<&Product>,
+        now: DateTime,
+        expires_at: DateTime,
+        session: Option<&mut ClientSession>,
+    ) -> Result<Option<PinCode>, Error> {
+        let filter = claimable(product, now)?;
+        let update = reserve(reservation_id, now, expires_at)?;
+
+        let options = FindOneAndUpdateOptions::builder()
+            .return_document(ReturnDocument::Before)
+            .build();
+        match session {
+            Some(session) => {
+                self.collection
+                    .find_one_and_update_with_session(filter, update, options, session)
+                    .await
+            }
+            None => self.collection.find_one_and_update(filter, update, options).await,
+        }
+    }
+
+    /// Up to `limit` PINs that `claim_available` would match right now. Nothing is
+    /// held until `claim_many` claims them.
+    pub async fn find_claimable(
+        &self,
+        product: Option<&Product>,
+        now: DateTime,
+        limit: i64,
+        session: Option<&mut ClientSession>,
+    ) -> Result<Vec<PinCode>, Error> {
+        let filter = claimable(product, now)?;
+        let options = FindOptions::builder().limit(limit).build();
+        match session {
+            Some(session) => {
+                let mut cursor = self.collection.find_with_session(filter, options, &mut *session).await?;
+                cursor.stream(session).try_collect().await
+            }
+            None => self.collection.find(filter, options).await?.try_collect().await,
+        }
+    }
+
+    /// Claims the PINs `ids` for `reservation_id` in one update, each only if it is
+    /// still available. Returns how many were claimed; fewer than `ids` means another
+    /// reservation got there first.
+    pub async fn claim_many(
+        &self,
+        ids: &[ObjectId],
+        reservation_id: ObjectId,
+        product: Option<&Product>,
+        now: DateTime,
+        expires_at: DateTime,
+        session: Option<&mut ClientSession>,
+    ) -> Result<u64, Error> {
+        let mut filter = claimable(product, now)?;
+        filter.insert("_id", doc! { "$in": ids });
+        let update = reserve(reservation_id, now, expires_at)?;
+
+        let result = match session {
+            Some(session) => {
+                self.collection
+                    .update_many_with_session(filter, update, None, session)
+                    .await?
+            }
+            None => self.collection.update_many(filter, update, None).await?,
+        };
+        Ok(result.modified_count)
+    }
 
+    /// Undoes `claim_available` and `claim_many` for every PIN `reservation_id`
+    /// claimed, when the reservation could not be completed.
+    pub async fn release_claims(&self, reservation_id: ObjectId) -> Result<u64, Error> {
         let filter = doc! {
-            "$or": [
-                { "status": to_bson(&PinStatus::Active).unwrap() },
-                {
-                    "$and": [
-                        { "status": to_bson(&PinStatus::Reserved).unwrap() },
-                        { "expiresAt": { "$lte": now } }
-                    ]
-                }
-            ]
+            "status": to_bson(&PinStatus::Reserved)?,
+            "reservationId": reservation_id
         };
-        self.collection.find_one(filter, None).await.ok().flatten()
+
+        let result = self.collection.update_many(filter, unreserve()?, None).await?;
+        Ok(result.modified_count)
     }
 
-    pub async fn find_by_reservation_id(&self, reservation_id: &str) -> Option<PinCode> {
-        let now = DateTime::now();
-        let object_id = ObjectId::parse_str(reservation_id).ok()?;
+    /// Hands the PINs of `reservation_id` back to the pool early, while it still
+    /// holds them. Returns how many were released.
+    pub async fn release_reservation(
+        &self,
+        reservation_id: ObjectId,
+        now: DateTime,
+        session: Option<&mut ClientSession>,
+    ) -> Result<u64, Error> {
+        let filter = doc! {
+            "status": to_bson(&PinStatus::Reserved)?,
+            "reservationId": reservation_id,
+            "expiresAt": { "$gt": now }
+        };
 
+        let result = match session {
+            Some(session) => {
+                self.collection
+                    .update_many_with_session(filter, unreserve()?, None, session)
+                    .await?
+            }
+            None => self.collection.update_many(filter, unreserve()?, None).await?,
+        };
+        Ok(result.modified_count)
+    }
+
+    /// Reserved PINs whose hold lapsed before `now`, oldest first.
+    pub async fn find_expired(&self, now: DateTime, limit: i64) -> Result<Vec<PinCode>, Error> {
         let filter = doc! {
-                "status": to_bson(&PinStatus::Reserved).unwrap(),
-                "reservationId": object_id ,
-                "expiresAt": { "$gte": now } 
+            "status": to_bson(&PinStatus::Reserved)?,
+            "expiresAt": { "$lte": now }
         };
-        self.collection.find_one(filter, None).await.ok().flatten()
+        let options = FindOptions::builder()
+            .sort(doc! { "expiresAt": 1 })
+            .limit(limit)
+            .build();
+        self.collection.find(filter, options).await?.try_collect().await
+    }
+
+    /// Returns a PIN to the pool once the hold of `reservation_id` has lapsed.
+    pub async fn expire_reservation(
+        &self,
+        id: ObjectId,
+        reservation_id: ObjectId,
+        now: DateTime,
+        session: Option<&mut ClientSession>,
+    ) -> Result<bool, Error> {
+        let filter = doc! {
+            "_id": id,
+            "status": to_bson(&PinStatus::Reserved)?,
+            "reservationId": reservation_id,
+            "expiresAt": { "$lte": now }
+        };
+
+        let result = match session {
+            Some(session) => {
+                self.collection
+                    .update_one_with_session(filter, unreserve()?, None, session)
+                    .await?
+            }
+            None => self.collection.update_one(filter, unreserve()?, None).await?,
+        };
+        Ok(result.modified_count == 1)
     }
 
-    pub async fn reserve_pincode(
+    /// Moves the end of a hold that has not lapsed yet. Returns how many PINs of
+    /// `reservation_id` were extended.
+    pub async fn extend_reservation(
         &self,
-        id: &str,
-        reservation_id: &str,
+        reservation_id: ObjectId,
         now: DateTime,
         expires_at: DateTime,
-    ) -> Result<ObjectId, Error> {
-        let object_id = ObjectId::parse_str(id).map_err(|e| {
-            Error::from(std::io::Error::new(
-                std::io::ErrorKind::InvalidInput,
-                format!("Invalid ObjectId string: {}", e),
-            ))
-        })?;
-        let rev_object_id = ObjectId::parse_str(reservation_id).map_err(|e| {
-            Error::from(std::io::Error::new(
-                std::io::ErrorKind::InvalidInput,
-                format!("Invalid ObjectId string: {}", e),
-            ))
-        })?;
+        session: Option<&mut ClientSession>,
+    ) -> Result<u64, Error> {
         let filter = doc! {
-            "_id": object_id
+            "status": to_bson(&PinStatus::Reserved)?,
+            "reservationId": reservation_id,
+            "expiresAt": { "$gt": now }
         };
 
         let update = doc! {
             "$set": {
-                "status": to_bson(&PinStatus::Reserved)?,
-                "reservedAt": to_bson(&now)?,
-                "reservationId": rev_object_id,
-                "expiresAt": to_bson(&expires_at)?
+                "expiresAt": expires_at
             }
         };
 
-        let result = self.collection.update_one(filter, update, None).await?;
+        let result = match session {
+            Some(session) => {
+                self.collection
+                    .update_many_with_session(filter, update, None, session)
+                    .await?
+            }
+            None => self.collection.update_many(filter, update, None).await?,
+        };
+        Ok(result.modified_count)
+    }
 
-        if result.matched_count == 1 {
-            Ok(object_id)
-        } else {
-            Err(Error::from(io::Error::new(
-                io::ErrorKind::NotFound,
-                "No matching document found to update",
-            )))
-        }
+    /// Reservations holding PINs of `batch_id`.
+    pub async fn find_batch_reservations(
+        &self,
+        batch_id: ObjectId,
+        session: Option<&mut ClientSession>,
+    ) -> Result<Vec<ObjectId>, Error> {
+        let filter = doc! {
+            "batchId": batch_id,
+            "status": to_bson(&PinStatus::Reserved)?
+        };
+        let ids = match session {
+            Some(session) => {
+                self.collection
+                    .distinct_with_session("reservationId", filter, None, session)
+                    .await?
+            }
+            None => self.collection.distinct("reservationId", filter, None).await?,
+        };
+        Ok(ids.iter().filter_map(|id| id.as_object_id()).collect())
+    }
+
+    /// Hands back every PIN the `reservation_ids` still hold, such as the PINs of
+    /// other batches left in a reservation a recall closed. Returns how many.
+    pub async fn release_reservations(
+        &self,
+        reservation_ids: &[ObjectId],
+        session: Option<&mut ClientSession>,
+    ) -> Result<u64, Error> {
+        let filter = doc! {
+            "status": to_bson(&PinStatus::Reserved)?,
+            "reservationId": { "$in": reservation_ids }
+        };
+
+        let result = match session {
+            Some(session) => {
+                self.collection
+                    .update_many_with_session(filter, unreserve()?, None, session)
+                    .await?
+            }
+            None => self.collection.update_many(filter, unreserve()?, None).await?,
+        };
+        Ok(result.modified_count)
+    }
+
+    /// Voids every PIN of `batch_id` that is in `status`. Returns how many were voided.
+    pub async fn void_batch(
+        &self,
+        batch_id: ObjectId,
+        status: PinStatus,
+        now: DateTime,
+        session: Option<&mut ClientSession>,
+    ) -> Result<u64, Error> {
+        let filter = doc! {
+            "batchId": batch_id,
+            "status": to_bson(&status)?
+        };
+
+        let update = doc! {
+            "$set": {
+                "status": to_bson(&PinStatus::Voided)?,
+                "voidedAt": now
+            }
+        };
+
+        let result = match session {
+            Some(session) => {
+                self.collection
+                    .update_many_with_session(filter, update, None, session)
+                    .await?
+            }
+            None => self.collection.update_many(filter, update, None).await?,
+        };
+        Ok(result.modified_count)
+    }
+
+    /// Number of PINs of `batch_id` in each status.
+    pub async fn count_by_status(&self, batch_id: ObjectId) -> Result<Vec<(String, i64)>, Error> {
+        let pipeline = vec![
+            doc! { "$match": { "batchId": batch_id } },
+            doc! { "$group": { "_id": "$status", "count": { "$sum": 1 } } },
+            doc! { "$sort": { "_id": 1 } },
+        ];
+        let groups: Vec<Document> = self.collection.aggregate(pipeline, None).await?.try_collect().await?;
+        Ok(groups
+            .iter()
+            .filter_map(|group| {
+                let status = group.get_str("_id").ok()?.to_string();
+                let count = match group.get("count")? {
+                    bson::Bson::Int32(count) => *count as i64,
+                    bson::Bson::Int64(count) => *count,
+                    _ => return None,
+                };
+                Some((status, count))
+            })
+            .collect())
+    }
+
+    pub async fn find_by_object_ids(&self, ids: &[ObjectId]) -> Result<Vec<PinCode>, Error> {
+        let options = FindOptions::builder().sort(doc! { "_id": 1 }).build();
+        self.collection
+            .find(doc! { "_id": { "$in": ids } }, options)
+            .await?
+            .try_collect()
+            .await
     }
 
-    pub async fn purchase_pincode(&self, id: &str, now: DateTime) -> Result<ObjectId, Error> {
-        let object_id = ObjectId::parse_str(id).map_err(|e| {
-            Error::from(std::io::Error::new(
-                std::io::ErrorKind::InvalidInput,
-                format!("Invalid ObjectId string: {}", e),
-            ))
-        })?;
+    /// Sells the PINs only while they are still held by `reservation_id` and the
+    /// hold has not lapsed. Returns how many were sold.
+    pub async fn purchase_pincodes(
+        &self,
+        ids: &[ObjectId],
+        reservation_id: ObjectId,
+        now: DateTime,
+        session: Option<&mut ClientSession>,
+    ) -> Result<u64, Error> {
         let filter = doc! {
-            "_id": object_id
+            "_id": { "$in": ids },
+            "status": to_bson(&PinStatus::Reserved)?,
+            "reservationId": reservation_id,
+            "expiresAt": { "$gt": now }
         };
 
         let update = doc! {
             "$set": {
                 "status": to_bson(&PinStatus::Purchased)?,
-                "purchasedAt": to_bson(&now)?,
+                "purchasedAt": now,
+            }
+        };
+
+        let result = match session {
+            Some(session) => {
+                self.collection
+                    .update_many_with_session(filter, update, None, session)
+                    .await?
+            }
+            None => self.collection.update_many(filter, update, None).await?,
+        };
+        Ok(result.modified_count)
+    }
+
+    /// Undoes `purchase_pincodes` when the sale could not be completed.
+    pub async fn revert_purchases(&self, ids: &[ObjectId], reservation_id: ObjectId) -> Result<u64, Error> {
+        let filter = doc! {
+            "_id": { "$in": ids },
+            "status": to_bson(&PinStatus::Purchased)?,
+            "reservationId": reservation_id
+        };
+
+        let update = doc! {
+            "$set": {
+                "status": to_bson(&PinStatus::Reserved)?
+            },
+            "$unset": {
+                "purchasedAt": ""
+            }
+        };
+
+        let result = self.collection.update_many(filter, update, None).await?;
+        Ok(result.modified_count)
+    }
+
+    pub async fn find_page(&self, after: Option<ObjectId>, limit: i64) -> Result<Vec<PinCode>, Error> {
+        let filter = match after {
+            Some(last_id) => doc! { "_id": { "$gt": last_id } },
+            None => doc! {},
+        };
+        let options = FindOptions::builder()
+            .sort(doc! { "_id": 1 })
+            .limit(limit)
+            .build();
+
+        let cursor = self.collection.find(filter, options).await?;
+        cursor.try_collect().await
+    }
+
+    /// Swaps the ciphertext only if it is still the one that was read, so a
+    /// concurrent writer is never overwritten with stale data.
+    pub async fn replace_encrypted(
+        &self,
+        id: ObjectId,
+        current: &str,
+        encrypted: &str,
+        blind_index: &str,
+    ) -> Result<bool, Error> {
+        let filter = doc! {
+            "_id": id,
+            "encrypted": current
+        };
+
+        let update = doc! {
+            "$set": {
+                "encrypted": encrypted,
+                "blindIndex": blind_index,
+                "aadVersion": AAD_VERSION
             }
         };
 
         let result = self.collection.update_one(filter, update, None).await?;
+        Ok(result.modified_count == 1)
+    }
 
-        if result.matched_count == 1 {
-            Ok(object_id)
-        } else {
-            Err(Error::from(io::Error::new(
-                io::ErrorKind::NotFound,
-                "No matching document found to update",
-            )))
+    pub async fn find_plaintext_page(
+        &self,
+        after: Option<ObjectId>,
+        limit: i64,
+    ) -> Result<Vec<PlaintextPinCode>, Error> {
+        let mut filter = doc! { "pincode": { "$exists": true } };
+        if let Some(last_id) = after {
+            filter.insert("_id", doc! { "$gt": last_id });
         }
+        let options = FindOptions::builder()
+            .sort(doc! { "_id": 1 })
+            .limit(limit)
+            .build();
+
+        let cursor = self
+            .collection
+            .clone_with_type::<PlaintextPinCode>()
+            .find(filter, options)
+            .await?;
+        cursor.try_collect().await
+    }
+
+    pub async fn strip_plaintext(&self, id: ObjectId, pincode: &str) -> Result<bool, Error> {
+        let filter = doc! {
+            "_id": id,
+            "pincode": pincode
+        };
+
+        let update = doc! {
+            "$unset": {
+                "pincode": ""
+            }
+        };
+
+        let result = self.collection.update_one(filter, update, None).await?;
+        Ok(result.modified_count == 1)
     }
 
     pub async fn insert_one(&self, mut pincode: PinCode) -> mongodb::error::Result<ObjectId> {
@@ -137,14 +540,60 @@ impl PinCodeRepository {
 
         match result.inserted_id {
             bson::Bson::ObjectId(oid) => Ok(oid),
-            _ => Err(mongodb::error::Error::from(std::io::Error::new(
-                std::io::ErrorKind::Other,
+            _ => Err(mongodb::error::Error::from(std::io::Error::other(
                 "Expected ObjectId in insert result",
             ))),
         }
     }
 }
 
+// Active PINs of `product` (of any product when `None`), or reserved ones whose hold lapsed
+fn claimable(product: Option<&Product>, now: DateTime) -> Result<Document, Error> {
+    let mut filter = doc! {
+        "$or": [
+            { "status": to_bson(&PinStatus::Active)? },
+            {
+                "$and": [
+                    { "status": to_bson(&PinStatus::Reserved)? },
+                    { "expiresAt": { "$lte": now } }
+                ]
+            }
+        ]
+    };
+    if let Some(product) = product {
+        filter.insert("operator", &product.operator);
+        filter.insert("denomination", product.denomination);
+        filter.insert("currency", &product.currency);
+    }
+    Ok(filter)
+}
+
+// Holds a PIN for `reservation_id` until `expires_at`
+fn reserve(reservation_id: ObjectId, now: DateTime, expires_at: DateTime) -> Result<Document, Error> {
+    Ok(doc! {
+        "$set": {
+            "status": to_bson(&PinStatus::Reserved)?,
+            "reservedAt": now,
+            "reservationId": reservation_id,
+            "expiresAt": expires_at
+        }
+    })
+}
+
+// Puts a reserved PIN back in the pool
+fn unreserve() -> Result<Document, Error> {
+    Ok(doc! {
+        "$set": {
+            "status": to_bson(&PinStatus::Active)?
+        },
+        "$unset": {
+            "reservedAt": "",
+            "reservationId": "",
+            "expiresAt": ""
+        }
+    })
+}
+
 #[derive(Debug, Clone)]
 pub struct PinCodeReservationRepository {
     collection: Collection<PinCodeReservation>,
@@ -157,36 +606,368 @@ impl PinCodeReservationRepository {
         }
     }
 
-    pub async fn find_by_id(&self, id: &str) -> Result<PinCodeReservation, Error> {
-        let filter = doc! { "_id": ObjectId::parse_str(id).unwrap() };
-        match self.collection.find_one(filter, None).await? {
-            Some(reservation) => {
-                println!("{:?}", reservation);
-                Ok(reservation)
-            }
-            None => Err(Error::from(io::Error::new(
-                io::ErrorKind::NotFound,
-                "PinCode not found",
-            ))),
-        }
+    pub async fn find_by_id(&self, id: ObjectId) -> Result<Option<PinCodeReservation>, Error> {
+        self.collection.find_one(doc! { "_id": id }, None).await
     }
 
     pub async fn insert_one(
         &self,
         mut pincode: PinCodeReservation,
+        session: Option<&mut ClientSession>,
     ) -> mongodb::error::Result<ObjectId> {
         if pincode.id.is_none() {
             pincode.id = Some(ObjectId::new());
         }
 
-        let result = self.collection.insert_one(pincode, None).await?;
+        let result = match session {
+            Some(session) => self.collection.insert_one_with_session(pincode, None, session).await?,
+            None => self.collection.insert_one(pincode, None).await?,
+        };
 
         match result.inserted_id {
             bson::Bson::ObjectId(oid) => Ok(oid),
-            _ => Err(mongodb::error::Error::from(std::io::Error::new(
-                std::io::ErrorKind::Other,
+            _ => Err(mongodb::error::Error::from(std::io::Error::other(
                 "Expected ObjectId in insert result",
             ))),
         }
     }
+
+    pub async fn mark_purchased(
+        &self,
+        id: ObjectId,
+        now: DateTime,
+        session: Option<&mut ClientSession>,
+    ) -> Result<(), Error> {
+        let filter = doc! { "_id": id };
+        let update = doc! {
+            "$set": {
+                "purchasedAt": now
+            }
+        };
+
+        match session {
+            Some(session) => self.collection.update_one_with_session(filter, update, None, session).await?,
+            None => self.collection.update_one(filter, update, None).await?,
+        };
+        Ok(())
+    }
+
+    pub async fn mark_expired(
+        &self,
+        id: ObjectId,
+        now: DateTime,
+        session: Option<&mut ClientSession>,
+    ) -> Result<(), Error> {
+        let filter = doc! { "_id": id };
+        let update = doc! {
+            "$set": {
+                "expiredAt": now
+            }
+        };
+
+        match session {
+            Some(session) => self.collection.update_one_with_session(filter, update, None, session).await?,
+            None => self.collection.update_one(filter, update, None).await?,
+        };
+        Ok(())
+    }
+
+    pub async fn mark_voided(
+        &self,
+        ids: &[ObjectId],
+        now: DateTime,
+        session: Option<&mut ClientSession>,
+    ) -> Result<(), Error> {
+        let filter = doc! { "_id": { "$in": ids } };
+        let update = doc! {
+            "$set": {
+                "voidedAt": now
+            }
+        };
+
+        match session {
+            Some(session) => self.collection.update_many_with_session(filter, update, None, session).await?,
+            None => self.collection.update_many(filter, update, None).await?,
+        };
+        Ok(())
+    }
+
+    pub async fn mark_released(
+        &self,
+        id: ObjectId,
+        now: DateTime,
+        session: Option<&mut ClientSession>,
+    ) -> Result<(), Error> {
+        let filter = doc! { "_id": id };
+        let update = doc! {
+            "$set": {
+                "releasedAt": now
+            }
+        };
+
+        match session {
+            Some(session) => self.collection.update_one_with_session(filter, update, None, session).await?,
+            None => self.collection.update_one(filter, update, None).await?,
+        };
+        Ok(())
+    }
+}
+
+#[derive(Debug, Clone)]
+pub struct JobCheckpointRepository {
+    collection: Collection<JobCheckpoint>,
+}
+
+impl JobCheckpointRepository {
+    pub fn new(context: &AppContext) -> Self {
+        Self {
+            collection: context.db_client.db().collection("job-checkpoints"),
+        }
+    }
+
+    pub async fn find_by_id(&self, id: &str) -> Result<Option<JobCheckpoint>, Error> {
+        self.collection.find_one(doc! { "_id": id }, None).await
+    }
+
+    pub async fn save(&self, checkpoint: &JobCheckpoint) -> Result<(), Error> {
+        let options = ReplaceOptions::builder().upsert(true).build();
+        self.collection
+            .replace_one(doc! { "_id": &checkpoint.id }, checkpoint, options)
+            .await?;
+        Ok(())
+    }
+}
+
+#[derive(Debug, Clone)]
+pub struct DataKeyRepository {
+    collection: Collection<DataKey>,
+}
+
+impl DataKeyRepository {
+    pub fn new(context: &AppContext) -> Self {
+        Self {
+            collection: context.db_client.db().collection("data-keys"),
+        }
+    }
+
+    pub async fn find_by_id(&self, id: ObjectId) -> Result<Option<DataKey>, Error> {
+        self.collection.find_one(doc! { "_id": id }, None).await
+    }
+
+    pub async fn find_all(&self) -> Result<Vec<DataKey>, Error> {
+        let options = FindOptions::builder().sort(doc! { "_id": 1 }).build();
+        let cursor = self.collection.find(doc! {}, options).await?;
+        cursor.try_collect().await
+    }
+
+    pub async fn insert_one(&self, data_key: &DataKey) -> Result<(), Error> {
+        self.collection.insert_one(data_key, None).await?;
+        Ok(())
+    }
+
+    pub async fn replace_wrapped(
+        &self,
+        id: ObjectId,
+        current: &str,
+        wrapped: &str,
+        now: DateTime,
+    ) -> Result<bool, Error> {
+        let filter = doc! {
+            "_id": id,
+            "wrapped": current
+        };
+
+        let update = doc! {
+            "$set": {
+                "wrapped": wrapped,
+                "rewrappedAt": now
+            }
+        };
+
+        let result = self.collection.update_one(filter, update, None).await?;
+        Ok(result.modified_count == 1)
+    }
+}
+
+#[derive(Debug, Clone)]
+pub struct BatchRepository {
+    collection: Collection<Batch>,
+}
+
+impl BatchRepository {
+    pub fn new(context: &AppContext) -> Self {
+        Self {
+            collection: context.db_client.db().collection("batches"),
+        }
+    }
+
+    pub async fn find_by_id(&self, id: ObjectId) -> Result<Option<Batch>, Error> {
+        self.collection.find_one(doc! { "_id": id }, None).await
+    }
+
+    /// Newest batches first, starting below `before` when given.
+    pub async fn find_page(&self, before: Option<ObjectId>, limit: i64) -> Result<Vec<Batch>, Error> {
+        let filter = match before {
+            Some(id) => doc! { "_id": { "$lt": id } },
+            None => doc! {},
+        };
+        let options = FindOptions::builder()
+            .sort(doc! { "_id": -1 })
+            .limit(limit)
+            .build();
+        self.collection.find(filter, options).await?.try_collect().await
+    }
+
+    pub async fn insert_one(&self, batch: &Batch) -> Result<(), Error> {
+        self.collection.insert_one(batch, None).await?;
+        Ok(())
+    }
+
+    pub async fn complete(
+        &self,
+        id: ObjectId,
+        count: i64,
+        checksum: Option<String>,
+        error: Option<String>,
+        now: DateTime,
+    ) -> Result<(), Error> {
+        let update = doc! {
+            "$set": {
+                "count": count,
+                "checksum": checksum,
+                "error": error,
+                "completedAt": now
+            }
+        };
+        self.collection.update_one(doc! { "_id": id }, update, None).await?;
+        Ok(())
+    }
+
+    /// Records the first recall of a batch. Returns false, leaving the original
+    /// record in place, when the batch was already recalled.
+    pub async fn mark_recalled(&self, id: ObjectId, reason: Option<String>, now: DateTime) -> Result<bool, Error> {
+        let filter = doc! {
+            "_id": id,
+            "recalledAt": null
+        };
+        let update = doc! {
+            "$set": {
+                "recalledAt": now,
+                "recallReason": reason
+            }
+        };
+        let result = self.collection.update_one(filter, update, None).await?;
+        Ok(result.modified_count == 1)
+    }
+}
+
+#[derive(Debug, Clone)]
+pub struct VaultCanaryRepository {
+    collection: Collection<VaultCanary>,
+}
+
+impl VaultCanaryRepository {
+    pub fn new(context: &AppContext) -> Self {
+        Self {
+            collection: context.db_client.db().collection("vault-meta"),
+        }
+    }
+
+    pub async fn find_by_id(&self, id: &str) -> Result<Option<VaultCanary>, Error> {
+        self.collection.find_one(doc! { "_id": id }, None).await
+    }
+
+    pub async fn insert_one(&self, canary: &VaultCanary) -> Result<(), Error> {
+        self.collection.insert_one(canary, None).await?;
+        Ok(())
+    }
+
+    pub async fn replace_encrypted(&self, id: &str, current: &str, encrypted: &str) -> Result<bool, Error> {
+        let filter = doc! {
+            "_id": id,
+            "encrypted": current
+        };
+
+        let update = doc! {
+            "$set": {
+                "encrypted": encrypted,
+                "updatedAt": DateTime::now()
+            }
+        };
+
+        let result = self.collection.update_one(filter, update, None).await?;
+        Ok(result.modified_count == 1)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use std::collections::HashSet;
+
+    use super::*;
+    use crate::application::env::{AppEnv, SecretSource};
+
+    const PINS: usize = 20;
+
+    async fn test_context() -> AppContext {
+        let mut env: AppEnv = serde_yaml::from_str(include_str!("../../../config.yml")).unwrap();
+        let key = |hex: String| Some(SecretSource::Loaded(SecretString::new(format!("hex:{}", hex))));
+        env.cipher.keyring.active.key = key("01".repeat(16) + &"23".repeat(16));
+        env.cipher.blind_index.key = key("45".repeat(16) + &"67".repeat(16));
+        env.datasource.database_name = format!("pin-vault-test-{}", ObjectId::new());
+
+        let mut context = AppContext::new(&env).unwrap();
+        context.db_client.init().await.unwrap();
+        context
+    }
+
+    // Needs the MongoDB from config.yml: cargo test -- --ignored
+    #[tokio::test(flavor = "multi_thread", worker_threads = 8)]
+    #[ignore]
+    async fn parallel_reservations_claim_distinct_pins() {
+        let context = test_context().await;
+        let repo = PinCodeRepository::new(&context);
+        for _ in 0..PINS {
+            repo.insert_one(PinCode {
+                id: None,
+                encrypted: String::new(),
+                status: PinStatus::Active,
+                created_at: Some(DateTime::now()),
+                purchased_at: None,
+                reserved_at: None,
+                reservation_id: None,
+                expires_at: None,
+                data_key_id: None,
+                blind_index: None,
+                operator: None,
+                denomination: None,
+                currency: None,
+                batch_id: None,
+                voided_at: None,
+                aad_version: None,
+            })
+            .await
+            .unwrap();
+        }
+
+        // Twice as many callers as PINs, all racing for the same documents
+        let now = DateTime::now();
+        let expires_at = DateTime::from_millis(now.timestamp_millis() + 60_000);
+        let claims = (0..PINS * 2).map(|_| {
+            let repo = repo.clone();
+            tokio::spawn(async move {
+                repo.claim_available(ObjectId::new(), None, now, expires_at, None).await.unwrap()
+            })
+        });
+        let claimed: Vec<PinCode> = futures::future::join_all(claims)
+            .await
+            .into_iter()
+            .filter_map(|claim| claim.unwrap())
+            .collect();
+        let distinct: HashSet<ObjectId> = claimed.iter().filter_map(|pin_code| pin_code.id).collect();
+
+        context.db_client.db().drop(None).await.unwrap();
+        assert_eq!(claimed.len(), PINS);
+        assert_eq!(distinct.len(), PINS);
+    }
 }
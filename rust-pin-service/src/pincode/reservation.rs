@@ -0,0 +1,689 @@
+use std::fmt;
+
+use bson::{DateTime, oid::ObjectId};
+use chrono::Duration;
+use futures::future::BoxFuture;
+use rand::Rng;
+use mongodb::ClientSession;
+use mongodb::error::{Error, TRANSIENT_TRANSACTION_ERROR, UNKNOWN_TRANSACTION_COMMIT_RESULT};
+use tonic::Status;
+
+use crate::application::AppContext;
+use crate::application::database::DatabaseClient;
+use crate::pincode::model::{PinCode, PinCodeReservation, PinStatus, Product};
+use crate::pincode::model::repository::{PinCodeRepository, PinCodeReservationRepository};
+
+const MAX_TRANSACTION_ATTEMPTS: usize = 3;
+// Attempts at a reservation that keeps losing PINs to concurrent ones
+const MAX_CLAIM_ATTEMPTS: usize = 5;
+const CLAIM_BACKOFF_MS: u64 = 20;
+
+// Raised inside a transaction to undo it when the outcome is a refusal, not a failure
+struct Rollback;
+
+// How one attempt at a reservation ended; also raised like `Rollback` to undo one
+#[derive(Clone, Copy)]
+enum Attempt {
+    Reserved,
+    // Fewer PINs are available than were asked for
+    Shortfall,
+    // Another reservation claimed some of the picked PINs first
+    Contended,
+}
+
+// PINs picked for a reservation, as they were before the claim, and how many it got
+struct Claim {
+    candidates: Vec<PinCode>,
+    claimed: u64,
+}
+
+impl Claim {
+    fn attempt(&self, count: usize) -> Attempt {
+        if self.candidates.len() < count {
+            Attempt::Shortfall
+        } else if self.claimed < count as u64 {
+            Attempt::Contended
+        } else {
+            Attempt::Reserved
+        }
+    }
+
+    // Reservations whose lapsed holds this claim took over, each once
+    fn lapsed(&self) -> Vec<ObjectId> {
+        let mut lapsed: Vec<ObjectId> = self.candidates.iter().filter_map(lapsed_reservation).collect();
+        lapsed.sort();
+        lapsed.dedup();
+        lapsed
+    }
+}
+
+/// Why a reservation could not be purchased, released or extended.
+#[derive(Debug)]
+pub enum ReservationError {
+    NotFound,
+    Expired,
+    AlreadyPurchased,
+    Released,
+    /// The PIN's batch was recalled.
+    Voided,
+    /// The hold lapsed and the PIN now belongs to another reservation.
+    Reassigned,
+    /// Concurrent reservations kept taking the PINs this one picked.
+    Contended,
+    Database(Error),
+}
+
+impl fmt::Display for ReservationError {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            ReservationError::NotFound => write!(f, "Reservation does not exist"),
+            ReservationError::Expired => write!(f, "Reservation has expired"),
+            ReservationError::AlreadyPurchased => write!(f, "Reservation was already purchased"),
+            ReservationError::Released => write!(f, "Reservation was released"),
+            ReservationError::Voided => write!(f, "Reserved PIN was recalled by its supplier"),
+            ReservationError::Reassigned => write!(f, "Reservation has expired and its PIN was reserved again"),
+            ReservationError::Contended => write!(f, "Too many concurrent reservations, try again"),
+            ReservationError::Database(e) => write!(f, "Failed to update reservation: {}", e),
+        }
+    }
+}
+
+impl std::error::Error for ReservationError {}
+
+impl From<Error> for ReservationError {
+    fn from(e: Error) -> Self {
+        ReservationError::Database(e)
+    }
+}
+
+impl From<ReservationError> for Status {
+    fn from(e: ReservationError) -> Self {
+        match e {
+            ReservationError::NotFound => Status::not_found(e.to_string()),
+            ReservationError::Expired => Status::failed_precondition(e.to_string()),
+            ReservationError::AlreadyPurchased => Status::already_exists(e.to_string()),
+            ReservationError::Released => Status::failed_precondition(e.to_string()),
+            ReservationError::Voided => Status::failed_precondition(e.to_string()),
+            ReservationError::Reassigned => Status::aborted(e.to_string()),
+            ReservationError::Contended => Status::aborted(e.to_string()),
+            // Write conflicts outlasted every retry; the caller may try again
+            ReservationError::Database(ref db) if db.contains_label(TRANSIENT_TRANSACTION_ERROR) => {
+                Status::aborted(e.to_string())
+            }
+            ReservationError::Database(_) => Status::internal(e.to_string()),
+        }
+    }
+}
+
+/// What a batch recall pulled from circulation.
+#[derive(Debug)]
+pub struct Recall {
+    pub active: u64,
+    pub reserved: u64,
+    // Reservations whose held PINs were voided
+    pub reservations: Vec<ObjectId>,
+    // PINs of other batches those reservations held, now back in the pool
+    pub released: u64,
+}
+
+/// Keeps `pincodes` and `reserved-pins` in step. On a replica set both writes of a
+/// reservation or a purchase commit in one transaction; on a standalone server the
+/// first write is undone when the second one fails.
+#[derive(Clone)]
+pub struct ReservationStore {
+    db_client: DatabaseClient,
+    pincode_repo: PinCodeRepository,
+    reservation_repo: PinCodeReservationRepository,
+}
+
+impl ReservationStore {
+    pub fn new(context: &AppContext) -> Self {
+        Self {
+            db_client: context.db_client.clone(),
+            pincode_repo: PinCodeRepository::new(context),
+            reservation_repo: PinCodeReservationRepository::new(context),
+        }
+    }
+
+    /// Claims `count` available PINs of `product` under one reservation ID and records
+    /// the reservation. All or nothing: `None` when fewer than `count` PINs are free.
+    /// An attempt that loses PINs to a concurrent reservation is retried with fresh
+    /// candidates; `Contended` once every attempt lost.
+    pub async fn reserve(
+        &self,
+        count: usize,
+        product: Option<Product>,
+        now: DateTime,
+        expires_at: DateTime,
+    ) -> Result<Option<ObjectId>, ReservationError> {
+        for attempt in 1..=MAX_CLAIM_ATTEMPTS {
+            let reservation_id = ObjectId::new();
+            let outcome = if self.db_client.transactions {
+                self.reserve_in_transaction(reservation_id, count, product.clone(), now, expires_at)
+                    .await
+            } else {
+                self.reserve_standalone(reservation_id, count, product.as_ref(), now, expires_at)
+                    .await
+            };
+            match outcome {
+                Ok(Attempt::Reserved) => return Ok(Some(reservation_id)),
+                Ok(Attempt::Shortfall) => return Ok(None),
+                Ok(Attempt::Contended) => {}
+                Err(e) if e.contains_label(TRANSIENT_TRANSACTION_ERROR) => {}
+                Err(e) => return Err(e.into()),
+            }
+            println!("Reserving {} PINs lost a race, attempt {}/{}", count, attempt, MAX_CLAIM_ATTEMPTS);
+            // Spread the retries of colliding callers apart
+            let backoff = rand::thread_rng().gen_range(0..CLAIM_BACKOFF_MS * attempt as u64);
+            tokio::time::sleep(std::time::Duration::from_millis(backoff)).await;
+        }
+        Err(ReservationError::Contended)
+    }
+
+    async fn reserve_in_transaction(
+        &self,
+        reservation_id: ObjectId,
+        count: usize,
+        product: Option<Product>,
+        now: DateTime,
+        expires_at: DateTime,
+    ) -> Result<Attempt, Error> {
+        let store = self.clone();
+        let reserved = self
+            .transaction(move |session| {
+                let store = store.clone();
+                let product = product.clone();
+                Box::pin(async move {
+                    let claim = store
+                        .claim(count, reservation_id, product.as_ref(), now, expires_at, Some(&mut *session))
+                        .await?;
+                    match claim.attempt(count) {
+                        Attempt::Reserved => {}
+                        attempt => return Err(Error::custom(attempt)),
+                    }
+                    for lapsed in claim.lapsed() {
+                        store
+                            .reservation_repo
+                            .mark_expired(lapsed, now, Some(&mut *session))
+                            .await?;
+                    }
+                    store
+                        .reservation_repo
+                        .insert_one(reservation(reservation_id, &claim.candidates, now), Some(session))
+                        .await?;
+                    Ok(())
+                })
+            })
+            .await;
+        match reserved {
+            Ok(()) => Ok(Attempt::Reserved),
+            Err(e) => match e.get_custom::<Attempt>() {
+                Some(attempt) => Ok(*attempt),
+                None => Err(e),
+            },
+        }
+    }
+
+    async fn reserve_standalone(
+        &self,
+        reservation_id: ObjectId,
+        count: usize,
+        product: Option<&Product>,
+        now: DateTime,
+        expires_at: DateTime,
+    ) -> Result<Attempt, Error> {
+        let claim = self.claim(count, reservation_id, product, now, expires_at, None).await;
+        let reserved = match &claim {
+            Ok(claim) => match claim.attempt(count) {
+                Attempt::Reserved => self
+                    .reservation_repo
+                    .insert_one(reservation(reservation_id, &claim.candidates, now), None)
+                    .await
+                    .map(|_| Attempt::Reserved),
+                attempt => Ok(attempt),
+            },
+            Err(e) => Err(e.clone()),
+        };
+        if let Ok(claim) = &claim
+            && claim.claimed > 0
+        {
+            // Those holds lapsed whether or not this reservation goes through
+            for lapsed in claim.lapsed() {
+                if let Err(e) = self.reservation_repo.mark_expired(lapsed, now, None).await {
+                    println!("Recording expiry of reservation {} failed: {}", lapsed, e);
+                }
+            }
+        }
+        if !matches!(reserved, Ok(Attempt::Reserved) | Ok(Attempt::Shortfall)) {
+            // Hand the PINs back instead of leaving them held by an unrecorded reservation
+            if let Err(e) = self.pincode_repo.release_claims(reservation_id).await {
+                println!("Releasing PINs of failed reservation {} failed: {}", reservation_id, e);
+            }
+        }
+        reserved
+    }
+
+    // Picks `count` available PINs and claims them in one guarded update. A single
+    // PIN is matched and claimed in one step instead, which never loses a race.
+    async fn claim(
+        &self,
+        count: usize,
+        reservation_id: ObjectId,
+        product: Option<&Product>,
+        now: DateTime,
+        expires_at: DateTime,
+        mut session: Option<&mut ClientSession>,
+    ) -> Result<Claim, Error> {
+        if count == 1 {
+            let claimed = self
+                .pincode_repo
+                .claim_available(reservation_id, product, now, expires_at, session)
+                .await?;
+            return Ok(Claim {
+                claimed: claimed.is_some().into(),
+                candidates: claimed.into_iter().collect(),
+            });
+        }
+
+        let candidates = self
+            .pincode_repo
+            .find_claimable(product, now, count as i64, session.as_deref_mut())
+            .await?;
+        if candidates.len() < count {
+            return Ok(Claim { candidates, claimed: 0 });
+        }
+        let ids: Vec<ObjectId> = candidates.iter().filter_map(|pin_code| pin_code.id).collect();
+        let claimed = self
+            .pincode_repo
+            .claim_many(&ids, reservation_id, product, now, expires_at, session)
+            .await?;
+        Ok(Claim { candidates, claimed })
+    }
+
+    /// PINs held by `reservation_id`, provided the hold is still live.
+    pub async fn find_reserved(&self, reservation_id: ObjectId, now: DateTime) -> Result<Vec<PinCode>, ReservationError> {
+        let reservation = self
+            .reservation_repo
+            .find_by_id(reservation_id)
+            .await?
+            .ok_or(ReservationError::NotFound)?;
+        check_closed(&reservation)?;
+        let ids = reservation.pin_ids();
+        let pin_codes = self.pincode_repo.find_by_object_ids(&ids).await?;
+        if ids.is_empty() || pin_codes.len() != ids.len() {
+            return Err(ReservationError::NotFound);
+        }
+        for pin_code in &pin_codes {
+            check_held(pin_code, reservation_id, now)?;
+        }
+        Ok(pin_codes)
+    }
+
+    /// Marks reserved PINs as sold and closes their reservation. The sale is one
+    /// conditional update, so a hold that lapsed since `find_reserved` is refused,
+    /// and either every PIN of the reservation is sold or none is.
+    pub async fn purchase(
+        &self,
+        pin_codes: &[PinCode],
+        reservation_id: ObjectId,
+        now: DateTime,
+    ) -> Result<(), ReservationError> {
+        let ids: Vec<ObjectId> = pin_codes.iter().filter_map(|pin_code| pin_code.id).collect();
+
+        if self.db_client.transactions {
+            let store = self.clone();
+            let txn_ids = ids.clone();
+            let purchased = self
+                .transaction(move |session| {
+                    let store = store.clone();
+                    let ids = txn_ids.clone();
+                    Box::pin(async move {
+                        let sold = store
+                            .pincode_repo
+                            .purchase_pincodes(&ids, reservation_id, now, Some(&mut *session))
+                            .await?;
+                        if sold != ids.len() as u64 {
+                            return Err(Error::custom(Rollback));
+                        }
+                        store
+                            .reservation_repo
+                            .mark_purchased(reservation_id, now, Some(session))
+                            .await
+                    })
+                })
+                .await;
+            return match purchased {
+                Ok(()) => Ok(()),
+                Err(e) if e.get_custom::<Rollback>().is_some() => Err(self.explain(&ids, reservation_id, now).await),
+                Err(e) => Err(e.into()),
+            };
+        }
+
+        let sold = self.pincode_repo.purchase_pincodes(&ids, reservation_id, now, None).await?;
+        let closed = if sold == ids.len() as u64 {
+            self.reservation_repo.mark_purchased(reservation_id, now, None).await
+        } else {
+            Ok(())
+        };
+        if sold != ids.len() as u64 || closed.is_err() {
+            // Put the sold PINs back on hold so the buyer can retry the purchase
+            if sold > 0
+                && let Err(revert) = self.pincode_repo.revert_purchases(&ids, reservation_id).await
+            {
+                println!("Reverting purchase of reservation {} failed: {}", reservation_id, revert);
+            }
+            return match closed {
+                Err(e) => Err(e.into()),
+                Ok(()) => Err(self.explain(&ids, reservation_id, now).await),
+            };
+        }
+        Ok(())
+    }
+
+    /// Returns held PINs to the pool before their hold runs out.
+    pub async fn release(&self, reservation_id: ObjectId, now: DateTime) -> Result<(), ReservationError> {
+        let pin_codes = self.find_reserved(reservation_id, now).await?;
+        let ids: Vec<ObjectId> = pin_codes.iter().filter_map(|pin_code| pin_code.id).collect();
+
+        let released = if self.db_client.transactions {
+            let store = self.clone();
+            self.transaction(move |session| {
+                let store = store.clone();
+                Box::pin(async move {
+                    let released = store
+                        .pincode_repo
+                        .release_reservation(reservation_id, now, Some(&mut *session))
+                        .await?;
+                    if released > 0 {
+                        store
+                            .reservation_repo
+                            .mark_released(reservation_id, now, Some(session))
+                            .await?;
+                    }
+                    Ok(released)
+                })
+            })
+            .await?
+        } else {
+            let released = self.pincode_repo.release_reservation(reservation_id, now, None).await?;
+            // The PINs are already back in the pool; a missing mark only affects bookkeeping
+            if released > 0
+                && let Err(e) = self.reservation_repo.mark_released(reservation_id, now, None).await
+            {
+                println!("Recording release of reservation {} failed: {}", reservation_id, e);
+            }
+            released
+        };
+
+        if released > 0 {
+            return Ok(());
+        }
+        Err(self.explain(&ids, reservation_id, now).await)
+    }
+
+    /// Moves the end of a hold that has not lapsed yet to `expires_at`, but never
+    /// more than `max_hold` past when its PINs were reserved, and returns the new
+    /// end. On a replica set every PIN is extended or none is; on a standalone
+    /// server a failure can leave some of them extended.
+    pub async fn extend(
+        &self,
+        reservation_id: ObjectId,
+        now: DateTime,
+        expires_at: DateTime,
+        max_hold: Duration,
+    ) -> Result<DateTime, ReservationError> {
+        let pin_codes = self.find_reserved(reservation_id, now).await?;
+        let ids: Vec<ObjectId> = pin_codes.iter().filter_map(|pin_code| pin_code.id).collect();
+        // Repeated extensions cannot keep the PINs out of the pool for good
+        let reserved_at = pin_codes.iter().filter_map(|pin_code| pin_code.reserved_at).min().unwrap_or(now);
+        let expires_at = expires_at.min(DateTime::from_chrono(reserved_at.to_chrono() + max_hold));
+
+        let extended = if self.db_client.transactions {
+            let store = self.clone();
+            let count = ids.len() as u64;
+            let extended = self
+                .transaction(move |session| {
+                    let store = store.clone();
+                    Box::pin(async move {
+                        let extended = store
+                            .pincode_repo
+                            .extend_reservation(reservation_id, now, expires_at, Some(session))
+                            .await?;
+                        if extended != count {
+                            return Err(Error::custom(Rollback));
+                        }
+                        Ok(extended)
+                    })
+                })
+                .await;
+            match extended {
+                Ok(extended) => extended,
+                Err(e) if e.get_custom::<Rollback>().is_some() => 0,
+                Err(e) => return Err(e.into()),
+            }
+        } else {
+            self.pincode_repo
+                .extend_reservation(reservation_id, now, expires_at, None)
+                .await?
+        };
+
+        if extended == ids.len() as u64 {
+            return Ok(expires_at);
+        }
+        Err(self.explain(&ids, reservation_id, now).await)
+    }
+
+    /// Returns PINs whose hold lapsed before `now` to the pool and closes their
+    /// reservations, `batch_size` at a time. Returns how many were reclaimed.
+    pub async fn expire_lapsed(&self, now: DateTime, batch_size: i64) -> Result<u64, Error> {
+        let mut reclaimed = 0;
+        loop {
+            let lapsed = self.pincode_repo.find_expired(now, batch_size).await?;
+            let mut expired = 0;
+            for pin_code in &lapsed {
+                if let (Some(id), Some(reservation_id)) = (pin_code.id, pin_code.reservation_id)
+                    && self.expire(id, reservation_id, now).await?
+                {
+                    expired += 1;
+                }
+            }
+            reclaimed += expired;
+            // Stop on a short page, or when a whole page was taken by other writers
+            if (lapsed.len() as i64) < batch_size || expired == 0 {
+                return Ok(reclaimed);
+            }
+        }
+    }
+
+    async fn expire(&self, id: ObjectId, reservation_id: ObjectId, now: DateTime) -> Result<bool, Error> {
+        if self.db_client.transactions {
+            let store = self.clone();
+            return self
+                .transaction(move |session| {
+                    let store = store.clone();
+                    Box::pin(async move {
+                        if !store
+                            .pincode_repo
+                            .expire_reservation(id, reservation_id, now, Some(&mut *session))
+                            .await?
+                        {
+                            return Ok(false);
+                        }
+                        store
+                            .reservation_repo
+                            .mark_expired(reservation_id, now, Some(session))
+                            .await?;
+                        Ok(true)
+                    })
+                })
+                .await;
+        }
+
+        let expired = self.pincode_repo.expire_reservation(id, reservation_id, now, None).await?;
+        // The PIN is already back in the pool; a missing mark only affects bookkeeping
+        if expired
+            && let Err(e) = self.reservation_repo.mark_expired(reservation_id, now, None).await
+        {
+            println!("Recording expiry of reservation {} failed: {}", reservation_id, e);
+        }
+        Ok(expired)
+    }
+
+    /// Voids every unsold PIN of `batch_id`, held ones included, and closes the
+    /// reservations holding them, releasing whatever else they held. On a replica
+    /// set this is one transaction. On a standalone server the steps run one after
+    /// another, so a failure partway leaves some PINs unvoided until the batch is
+    /// recalled again.
+    pub async fn recall(&self, batch_id: ObjectId, now: DateTime) -> Result<Recall, Error> {
+        if self.db_client.transactions {
+            let store = self.clone();
+            return self
+                .transaction(move |session| {
+                    let store = store.clone();
+                    Box::pin(async move {
+                        let reservations = store
+                            .pincode_repo
+                            .find_batch_reservations(batch_id, Some(&mut *session))
+                            .await?;
+                        let active = store
+                            .pincode_repo
+                            .void_batch(batch_id, PinStatus::Active, now, Some(&mut *session))
+                            .await?;
+                        let reserved = store
+                            .pincode_repo
+                            .void_batch(batch_id, PinStatus::Reserved, now, Some(&mut *session))
+                            .await?;
+                        let mut released = 0;
+                        if !reservations.is_empty() {
+                            released = store
+                                .pincode_repo
+                                .release_reservations(&reservations, Some(&mut *session))
+                                .await?;
+                            store
+                                .reservation_repo
+                                .mark_voided(&reservations, now, Some(session))
+                                .await?;
+                        }
+                        Ok(Recall { active, reserved, reservations, released })
+                    })
+                })
+                .await;
+        }
+
+        // Active PINs first, so no new hold can be taken on the batch meanwhile
+        let active = self.pincode_repo.void_batch(batch_id, PinStatus::Active, now, None).await?;
+        let reservations = self.pincode_repo.find_batch_reservations(batch_id, None).await?;
+        let reserved = self.pincode_repo.void_batch(batch_id, PinStatus::Reserved, now, None).await?;
+        let mut released = 0;
+        if !reservations.is_empty() {
+            // Mixed reservations can no longer be purchased, so their other PINs go back
+            released = self.pincode_repo.release_reservations(&reservations, None).await?;
+            // The PINs are already voided; a missing mark only affects bookkeeping
+            if let Err(e) = self.reservation_repo.mark_voided(&reservations, now, None).await {
+                println!("Recording recall of batch {} on its reservations failed: {}", batch_id, e);
+            }
+        }
+        Ok(Recall { active, reserved, reservations, released })
+    }
+
+    // A conditional update on the PINs `ids` fell short; look again to tell the caller why
+    async fn explain(&self, ids: &[ObjectId], reservation_id: ObjectId, now: DateTime) -> ReservationError {
+        if let Ok(Some(reservation)) = self.reservation_repo.find_by_id(reservation_id).await
+            && let Err(e) = check_closed(&reservation)
+        {
+            return e;
+        }
+        match self.pincode_repo.find_by_object_ids(ids).await {
+            Ok(current) if current.len() < ids.len() => ReservationError::NotFound,
+            Ok(current) => current
+                .iter()
+                .find_map(|pin_code| check_held(pin_code, reservation_id, now).err())
+                .unwrap_or(ReservationError::Expired),
+            Err(e) => e.into(),
+        }
+    }
+
+    // Runs `body` in a transaction, retrying the whole attempt on transient errors
+    async fn transaction<T, F>(&self, mut body: F) -> Result<T, Error>
+    where
+        F: FnMut(&mut ClientSession) -> BoxFuture<'_, Result<T, Error>>,
+    {
+        let mut session = self.db_client.start_session().await?;
+        let mut attempt = 0;
+        loop {
+            attempt += 1;
+            session.start_transaction(None).await?;
+            let result = match body(&mut session).await {
+                Ok(value) => commit(&mut session).await.map(|_| value),
+                Err(e) => {
+                    let _ = session.abort_transaction().await;
+                    Err(e)
+                }
+            };
+            match result {
+                Err(e) if e.contains_label(TRANSIENT_TRANSACTION_ERROR) && attempt < MAX_TRANSACTION_ATTEMPTS => {
+                    println!("Retrying transaction after transient error: {}", e);
+                }
+                result => return result,
+            }
+        }
+    }
+}
+
+async fn commit(session: &mut ClientSession) -> Result<(), Error> {
+    let mut attempt = 0;
+    loop {
+        attempt += 1;
+        match session.commit_transaction().await {
+            Err(e) if e.contains_label(UNKNOWN_TRANSACTION_COMMIT_RESULT) && attempt < MAX_TRANSACTION_ATTEMPTS => {}
+            result => return result,
+        }
+    }
+}
+
+fn reservation(reservation_id: ObjectId, pin_codes: &[PinCode], now: DateTime) -> PinCodeReservation {
+    PinCodeReservation {
+        id: Some(reservation_id),
+        pincode_id: None,
+        pincode_ids: pin_codes.iter().filter_map(|pin_code| pin_code.id).collect(),
+        reserved_at: now,
+        purchased_at: None,
+        released_at: None,
+        expired_at: None,
+        voided_at: None,
+    }
+}
+
+// Reservation whose lapsed hold `claim_available` just took over, if any
+fn lapsed_reservation(claimed: &PinCode) -> Option<ObjectId> {
+    match claimed.status {
+        PinStatus::Reserved => claimed.reservation_id,
+        _ => None,
+    }
+}
+
+fn check_closed(reservation: &PinCodeReservation) -> Result<(), ReservationError> {
+    if reservation.purchased_at.is_some() {
+        Err(ReservationError::AlreadyPurchased)
+    } else if reservation.released_at.is_some() {
+        Err(ReservationError::Released)
+    } else if reservation.voided_at.is_some() {
+        Err(ReservationError::Voided)
+    } else if reservation.expired_at.is_some() {
+        Err(ReservationError::Expired)
+    } else {
+        Ok(())
+    }
+}
+
+fn check_held(pin_code: &PinCode, reservation_id: ObjectId, now: DateTime) -> Result<(), ReservationError> {
+    if pin_code.reservation_id != Some(reservation_id) {
+        return Err(ReservationError::Reassigned);
+    }
+    match pin_code.status {
+        PinStatus::Purchased => Err(ReservationError::AlreadyPurchased),
+        PinStatus::Voided => Err(ReservationError::Voided),
+        PinStatus::Reserved if pin_code.expires_at.is_some_and(|expires_at| expires_at > now) => Ok(()),
+        _ => Err(ReservationError::Expired),
+    }
+}
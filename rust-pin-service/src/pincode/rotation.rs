@@ -0,0 +1,102 @@
+use bson::DateTime;
+use mongodb::error::Error;
+
+use crate::application::database::utils::is_duplicate_key;
+use crate::pincode::datakey::DataKeyError;
+use crate::pincode::model::{JobCheckpoint, PinCode};
+use crate::pincode::model::repository::{JobCheckpointRepository, PinCodeRepository};
+
+pub const REENCRYPTION_JOB_ID: &str = "reencrypt-pincodes";
+pub const DEFAULT_BATCH_SIZE: i64 = 500;
+
+/// Walks `pincodes` in `_id` order and reseals every ciphertext that is not bound
+/// to its record, product and batch under the active key, filling in the blind
+/// index of records that predate it. Progress is checkpointed after each batch,
+/// so an interrupted run picks up where it stopped unless `restart` is set.
+pub async fn reencrypt_pin_codes(
+    pincode_repo: PinCodeRepository,
+    checkpoint_repo: JobCheckpointRepository,
+    batch_size: i64,
+    restart: bool,
+) -> Result<JobCheckpoint, Error> {
+    let now = DateTime::now();
+    let mut checkpoint = match checkpoint_repo.find_by_id(REENCRYPTION_JOB_ID).await? {
+        Some(checkpoint) if !restart && !checkpoint.completed => {
+            println!("Resuming re-encryption after {:?}", checkpoint.last_id);
+            checkpoint
+        }
+        _ => JobCheckpoint {
+            id: REENCRYPTION_JOB_ID.into(),
+            last_id: None,
+            migrated: 0,
+            skipped: 0,
+            failed: 0,
+            unbound: 0,
+            completed: false,
+            started_at: now,
+            updated_at: now,
+        },
+    };
+
+    loop {
+        let page = pincode_repo.find_page(checkpoint.last_id, batch_size).await?;
+        if page.is_empty() {
+            break;
+        }
+
+        for pin_code in &page {
+            let Some(id) = pin_code.id else { continue };
+            let unbound = !pincode_repo.is_bound(pin_code);
+            match reencrypt(&pincode_repo, pin_code).await {
+                Ok(None) => checkpoint.skipped += 1,
+                Ok(Some((encrypted, blind_index))) => {
+                    match pincode_repo
+                        .replace_encrypted(id, &pin_code.encrypted, &encrypted, &blind_index)
+                        .await
+                    {
+                        Ok(true) => checkpoint.migrated += 1,
+                        // Rewritten by someone else since we read it
+                        Ok(false) => checkpoint.skipped += 1,
+                        Err(e) if is_duplicate_key(&e) => {
+                            println!("Re-encryption found {} duplicates another PIN", id);
+                            checkpoint.failed += 1;
+                            checkpoint.unbound += i64::from(unbound);
+                        }
+                        Err(e) => return Err(e),
+                    }
+                }
+                Err(e) => {
+                    println!("Re-encryption failed for {}: {}", id, e);
+                    checkpoint.failed += 1;
+                    checkpoint.unbound += i64::from(unbound);
+                }
+            }
+        }
+
+        checkpoint.last_id = page.last().and_then(|pin_code| pin_code.id);
+        checkpoint.updated_at = DateTime::now();
+        checkpoint_repo.save(&checkpoint).await?;
+        println!(
+            "Re-encryption progress: migrated {}, skipped {}, failed {} ({} left unbound)",
+            checkpoint.migrated, checkpoint.skipped, checkpoint.failed, checkpoint.unbound
+        );
+    }
+
+    checkpoint.completed = true;
+    checkpoint.updated_at = DateTime::now();
+    checkpoint_repo.save(&checkpoint).await?;
+    Ok(checkpoint)
+}
+
+// Returns the new ciphertext and blind index, or `None` when both are current.
+async fn reencrypt(
+    repo: &PinCodeRepository,
+    pin_code: &PinCode,
+) -> Result<Option<(String, String)>, DataKeyError> {
+    if pin_code.blind_index.is_some() && repo.is_current(pin_code).await? {
+        return Ok(None);
+    }
+    let pin = repo.open(pin_code).await?;
+    let encrypted = repo.seal(pin_code, &pin).await?;
+    Ok(Some((encrypted, repo.blind_index(&pin))))
+}
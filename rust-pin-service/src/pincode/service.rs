@@ -1,26 +1,48 @@
-use bson::DateTime;
+use bson::{DateTime, oid::ObjectId};
 use chrono::Duration;
-use futures::future::join_all;
+use futures::{Stream, StreamExt, future::join_all};
+use sha2::{Digest, Sha256};
+use tokio::task::JoinHandle;
 use tonic::{Request, Response, Status};
+use x509_parser::prelude::{FromDer, X509Certificate};
 
 use crate::application::AppContext;
-use crate::pincode::model::repository::{PinCodeRepository, PinCodeReservationRepository};
-use crate::pincode::model::{PinCode, PinCodeReservation, PinStatus};
-use crate::pincode::utils;
+use crate::application::env::ReservationConf;
+use crate::application::database::utils::is_duplicate_key;
+use crate::pincode::model::repository::{BatchRepository, JobCheckpointRepository, PinCodeRepository};
+use crate::pincode::model::{AAD_VERSION, Batch, BatchSource, PinCode, PinStatus, Product};
+use crate::pincode::reservation::ReservationStore;
+use crate::pincode::{migration, rotation, utils};
 use crate::vault::pin_code_vault_service_server::PinCodeVaultService;
 
-use crate::cipher::Cipher;
+use crate::cipher::{Cipher, SecretString};
+use crate::pincode::datakey::DataKeyError;
+use crate::vault;
 use crate::vault::{
-    GenerationRequest, IdRequest, PinCodeChunk, PinCodeResponse, ReservationResponse,
-    StatusResponse,
+    BatchListRequest, BatchListResponse, BatchStatusResponse, BulkReservationRequest, ExtensionRequest, GenerationRequest, IdRequest, MigrationRequest, MigrationResponse, PinCodeChunk,
+    PinCodeResponse, RecallRequest, RecallResponse, ReencryptionRequest, ReencryptionResponse, ReencryptionStatusResponse, ReservationRequest,
+    ReservationResponse, StatusResponse,
 };
 
+use std::pin::Pin;
 use std::sync::Arc;
+use tokio::sync::Mutex;
+
+// Drawing a PIN that is already stored is rare, so a few retries are plenty
+const MAX_GENERATE_ATTEMPTS: usize = 5;
+// Every PIN of a bulk reservation is claimed within one transaction
+const MAX_BULK_RESERVATION: i32 = 1000;
+const DEFAULT_BATCH_PAGE: i32 = 50;
+const MAX_BATCH_PAGE: i32 = 500;
 
 pub struct RustPinCodeVault {
     pub cipher: Option<Arc<dyn Cipher + Send + Sync>>,
     pincode_repo: PinCodeRepository,
-    reservation_repo: PinCodeReservationRepository,
+    reservations: ReservationStore,
+    reservation_conf: ReservationConf,
+    batch_repo: BatchRepository,
+    checkpoint_repo: JobCheckpointRepository,
+    reencryption_lock: Arc<Mutex<()>>,
 }
 
 #[derive(Clone, PartialEq, ::prost::Message)]
@@ -31,9 +53,230 @@ impl RustPinCodeVault {
         Self {
             cipher: context.cipher.clone(),
             pincode_repo: PinCodeRepository::new(context),
-            reservation_repo: PinCodeReservationRepository::new(context),
+            reservations: ReservationStore::new(context),
+            reservation_conf: context.env.reservation.clone(),
+            batch_repo: BatchRepository::new(context),
+            checkpoint_repo: JobCheckpointRepository::new(context),
+            reencryption_lock: Arc::new(Mutex::new(())),
+        }
+    }
+
+    // End of a hold starting `now`; `None` for a negative TTL
+    fn expires_at(&self, now: DateTime, ttl_seconds: i32) -> Option<DateTime> {
+        let ttl = self.reservation_conf.ttl_secs(u32::try_from(ttl_seconds).ok()?);
+        Some(DateTime::from_chrono(now.to_chrono() + Duration::seconds(ttl.into())))
+    }
+
+    async fn reserve(
+        &self,
+        count: usize,
+        ttl_seconds: i32,
+        product: Option<vault::Product>,
+    ) -> Result<Response<ReservationResponse>, Status> {
+        let product = product.map(to_product).transpose().map_err(Status::invalid_argument)?;
+        let now = DateTime::now();
+        let expires_at = self
+            .expires_at(now, ttl_seconds)
+            .ok_or_else(|| Status::invalid_argument("ttl_seconds must not be negative"))?;
+        let reserved = self.reservations.reserve(count, product, now, expires_at).await?;
+
+        match reserved {
+            Some(rev_id) => Ok(Response::new(ReservationResponse {
+                success: true,
+                message: match count {
+                    1 => "PIN reserved".into(),
+                    count => format!("{} PINs reserved", count),
+                },
+                id: rev_id.to_hex(), // Convert ObjectId to hex string
+                expires_at: Some(expires_at.to_system_time().into()),
+            })),
+            None => Ok(Response::new(ReservationResponse {
+                success: false,
+                message: "No PIN Available!".into(),
+                id: "".into(),
+                expires_at: None,
+            })),
+        }
+    }
+
+    // Records a new batch under a fresh data key before any of its PINs are stored
+    async fn start_batch(
+        &self,
+        source: BatchSource,
+        product: Product,
+        supplier: Option<String>,
+        file_name: Option<String>,
+        uploader: Option<String>,
+    ) -> Result<Batch, Status> {
+        let batch = Batch {
+            id: ObjectId::new(),
+            source,
+            supplier,
+            file_name,
+            checksum: None,
+            uploader,
+            product,
+            data_key_id: self.pincode_repo.keys().create().await?,
+            count: 0,
+            created_at: DateTime::now(),
+            completed_at: None,
+            recalled_at: None,
+            recall_reason: None,
+            error: None,
+        };
+        self.batch_repo
+            .insert_one(&batch)
+            .await
+            .map_err(|e| Status::internal(format!("Failed to record batch: {}", e)))?;
+        println!("Started {} batch {}", batch.source, batch.id);
+        Ok(batch)
+    }
+
+    async fn complete_batch(
+        &self,
+        batch: &Batch,
+        count: i64,
+        checksum: Option<String>,
+        error: Option<&DataKeyError>,
+    ) -> Result<(), Status> {
+        self.batch_repo
+            .complete(batch.id, count, checksum, error.map(ToString::to_string), DateTime::now())
+            .await
+            .map_err(|e| Status::internal(format!("Failed to complete batch {}: {}", batch.id, e)))
+    }
+
+    // A recall that landed while the batch was still being stored missed the PINs
+    // inserted after it, so void those too
+    async fn settle_recall(&self, batch: &Batch) -> Result<(), Status> {
+        let recalled = self
+            .batch_repo
+            .find_by_id(batch.id)
+            .await
+            .map_err(|e| Status::internal(format!("Failed to load batch: {}", e)))?
+            .is_some_and(|batch| batch.recalled_at.is_some());
+        if recalled {
+            let recall = self
+                .reservations
+                .recall(batch.id, DateTime::now())
+                .await
+                .map_err(|e| Status::internal(format!("Failed to recall batch: {}", e)))?;
+            println!(
+                "Batch {} was recalled while storing: {} more PINs voided",
+                batch.id,
+                recall.active + recall.reserved
+            );
         }
+        Ok(())
+    }
+
+    async fn decrypt_pin(&self, pin_code: &PinCode) -> Result<SecretString, Status> {
+        self.pincode_repo.open(pin_code).await.map_err(|e| match e {
+            DataKeyError::Cipher(e) => {
+                Status::data_loss(format!("Stored PIN cannot be decrypted: {}", e))
+            }
+            e => e.into(),
+        })
+    }
+}
+
+// A product as sent by a client; every field is required
+fn to_product(product: vault::Product) -> Result<Product, String> {
+    let operator = product.operator.trim();
+    let currency = product.currency.trim().to_uppercase();
+    if operator.is_empty() {
+        return Err("product operator is required".into());
     }
+    if product.denomination <= 0 {
+        return Err("product denomination must be positive".into());
+    }
+    if currency.len() != 3 || !currency.chars().all(|c| c.is_ascii_alphabetic()) {
+        return Err("product currency must be a three-letter ISO 4217 code".into());
+    }
+    Ok(Product {
+        operator: operator.to_string(),
+        denomination: product.denomination,
+        currency,
+    })
+}
+
+fn required_product(product: Option<vault::Product>) -> Result<Product, String> {
+    to_product(product.ok_or("product is required")?)
+}
+
+// Who sent `request`, as named by the client certificate it authenticated with;
+// `None` when the vault serves without mutual TLS
+fn caller<T>(request: &Request<T>) -> Option<String> {
+    let certs = request.peer_certs()?;
+    let (_, cert) = X509Certificate::from_der(certs.first()?.get_ref()).ok()?;
+    let subject = cert.subject();
+    let common_name = subject.iter_common_name().next().and_then(|cn| cn.as_str().ok());
+    Some(common_name.map_or_else(|| subject.to_string(), str::to_string))
+}
+
+fn non_empty(value: String) -> Option<String> {
+    let value = value.trim();
+    (!value.is_empty()).then(|| value.to_string())
+}
+
+fn timestamp(value: DateTime) -> prost_types::Timestamp {
+    value.to_system_time().into()
+}
+
+fn batch_response(batch: &Batch) -> vault::Batch {
+    vault::Batch {
+        id: batch.id.to_hex(),
+        source: batch.source.to_string(),
+        supplier: batch.supplier.clone().unwrap_or_default(),
+        file_name: batch.file_name.clone().unwrap_or_default(),
+        checksum: batch.checksum.clone().unwrap_or_default(),
+        count: batch.count,
+        uploader: batch.uploader.clone().unwrap_or_default(),
+        product: Some(vault::Product {
+            operator: batch.product.operator.clone(),
+            denomination: batch.product.denomination,
+            currency: batch.product.currency.clone(),
+        }),
+        created_at: Some(timestamp(batch.created_at)),
+        completed_at: batch.completed_at.map(timestamp),
+        recalled_at: batch.recalled_at.map(timestamp),
+        recall_reason: batch.recall_reason.clone().unwrap_or_default(),
+        error: batch.error.clone().unwrap_or_default(),
+    }
+}
+
+async fn new_pin_code(
+    repo: &PinCodeRepository,
+    batch: &Batch,
+    pin: SecretString,
+) -> Result<PinCode, DataKeyError> {
+    let mut pin_code = PinCode {
+        encrypted: String::new(),
+        status: PinStatus::Active,
+        created_at: Some(DateTime::now()),
+        expires_at: None,
+        // Assigned up front because the ciphertext is bound to it
+        id: Some(ObjectId::new()),
+        purchased_at: None,
+        reservation_id: None,
+        reserved_at: None,
+        data_key_id: Some(batch.data_key_id),
+        blind_index: Some(repo.blind_index(&pin)),
+        operator: Some(batch.product.operator.clone()),
+        denomination: Some(batch.product.denomination),
+        currency: Some(batch.product.currency.clone()),
+        batch_id: Some(batch.id),
+        voided_at: None,
+        aad_version: Some(AAD_VERSION),
+    };
+    pin_code.encrypted = repo.seal(&pin_code, &pin).await?;
+    Ok(pin_code)
+}
+
+fn spawn_insert(
+    repo: PinCodeRepository,
+    pin_code: PinCode,
+) -> JoinHandle<mongodb::error::Result<ObjectId>> {
+    tokio::spawn(async move { repo.insert_one(pin_code).await })
 }
 
 #[tonic::async_trait]
@@ -42,6 +285,7 @@ impl PinCodeVaultService for RustPinCodeVault {
         &self,
         request: Request<tonic::Streaming<PinCodeChunk>>,
     ) -> Result<Response<StatusResponse>, Status> {
+        let uploader = caller(&request);
         let mut stream = request.into_inner();
         let mut tasks = Vec::new();
 
@@ -53,6 +297,11 @@ impl PinCodeVaultService for RustPinCodeVault {
         let repo = self.pincode_repo.clone();
 
         let mut line_buffer = String::new(); // stores leftover partial line
+        let mut line_no = 0usize;
+        let mut header = None;
+        let mut checksum = Sha256::new();
+        // Every line is opened before anything is stored, so a corrupt file stores nothing
+        let mut pins = Vec::new();
 
         while let Some(chunk) = stream.message().await? {
             println!(
@@ -60,6 +309,16 @@ impl PinCodeVaultService for RustPinCodeVault {
                 chunk.file_name,
                 chunk.content.len()
             );
+            // The first chunk says what the whole file is and where it came from
+            if header.is_none() {
+                let product = required_product(chunk.product).map_err(Status::invalid_argument)?;
+                header = Some((
+                    product,
+                    non_empty(chunk.supplier),
+                    non_empty(chunk.file_name),
+                ));
+            }
+            checksum.update(&chunk.content);
 
             let chunk_str = String::from_utf8(chunk.content)
                 .map_err(|_| Status::invalid_argument("Chunk is not valid UTF-8"))?;
@@ -71,69 +330,87 @@ impl PinCodeVaultService for RustPinCodeVault {
             while let Some(idx) = line_buffer.find('\n') {
                 let line = line_buffer[..idx].trim_end().to_string();
                 line_buffer = line_buffer[idx + 1..].to_string(); // cut off processed line
+                line_no += 1;
 
-                let cipher = cipher.clone();
-                let repo = repo.clone();
-
-                let task = tokio::spawn(async move {
-                    println!("Line: {}", line);
-                    let pin = cipher.enc_decrypt(line.clone());
-
-                    let pin_code = PinCode {
-                        pincode: pin,
-                        encrypted: line,
-                        status: PinStatus::Active,
-                        created_at: Some(DateTime::now()),
-                        expires_at: None,
-                        id: None,
-                        purchased_at: None,
-                        reservation_id: None,
-                        reserved_at: None,
-                    };
-
-                    if let Err(e) = repo.insert_one(pin_code).await {
-                        println!("Insert failed: {:?}", e);
-                    }
-                });
+                if line.is_empty() {
+                    continue;
+                }
+                println!("Line: {}", line);
+                // Supplier files are unbound, so open and reseal against the new record
+                let pin = cipher
+                    .enc_decrypt(line)
+                    .map_err(|e| Status::invalid_argument(format!("Line {}: {}", line_no, e)))?;
+                pins.push(pin);
+            }
+        }
 
-                tasks.push(task);
+        let line = line_buffer.trim_end().to_string();
+        if !line.is_empty() {
+            line_no += 1;
+            println!("Final Line: {}", line);
+            let pin = cipher
+                .enc_decrypt(line)
+                .map_err(|e| Status::invalid_argument(format!("Line {}: {}", line_no, e)))?;
+            pins.push(pin);
+        }
+
+        // An empty upload never gets far enough to start a batch
+        let batch = match header {
+            Some((product, supplier, file_name)) => Some(
+                self.start_batch(BatchSource::Upload, product, supplier, file_name, uploader)
+                    .await?,
+            ),
+            None => None,
+        };
+        let mut error = None;
+        if let Some(batch) = &batch {
+            for pin in pins {
+                match new_pin_code(&repo, batch, pin).await {
+                    Ok(pin_code) => tasks.push(spawn_insert(repo.clone(), pin_code)),
+                    // Stop sealing, but still settle the inserts already under way
+                    Err(e) => {
+                        println!("Sealing failed: {}", e);
+                        error = Some(e);
+                        break;
+                    }
+                }
             }
         }
 
-        if !line_buffer.is_empty() {
-            let line = line_buffer.trim_end().to_string();
-            let cipher = cipher.clone();
-            let repo = repo.clone();
-
-            let task = tokio::spawn(async move {
-                println!("Final Line: {}", line);
-                let pin = cipher.enc_decrypt(line.clone());
-
-                let pin_code = PinCode {
-                    pincode: pin,
-                    encrypted: line,
-                    status: PinStatus::Active,
-                    created_at: Some(DateTime::now()),
-                    expires_at: None,
-                    id: None,
-                    purchased_at: None,
-                    reservation_id: None,
-                    reserved_at: None,
-                };
-
-                if let Err(e) = repo.insert_one(pin_code).await {
+        let (mut inserted, mut duplicates, mut failed) = (0, 0, 0);
+        for result in futures::future::join_all(tasks).await {
+            match result {
+                Ok(Ok(_)) => inserted += 1,
+                // The unique blind index rejected a PIN that is already stored
+                Ok(Err(e)) if is_duplicate_key(&e) => duplicates += 1,
+                Ok(Err(e)) => {
                     println!("Insert failed: {:?}", e);
+                    failed += 1;
                 }
-            });
-
-            tasks.push(task);
+                Err(e) => {
+                    println!("Insert task failed: {}", e);
+                    failed += 1;
+                }
+            }
         }
 
-        let _ = futures::future::join_all(tasks).await;
+        let mut message = format!(
+            "Upload complete: {} PIN code(s) stored, {} duplicate(s) rejected, {} failed",
+            inserted, duplicates, failed
+        );
+        if let Some(batch) = &batch {
+            let checksum = Some(hex::encode(checksum.finalize()));
+            self.complete_batch(batch, inserted, checksum, error.as_ref()).await?;
+            self.settle_recall(batch).await?;
+            message.push_str(&format!(" (batch {})", batch.id));
+        }
+        if let Some(e) = error {
+            return Err(e.into());
+        }
 
         Ok(Response::new(StatusResponse {
-            success: true,
-            message: "Upload complete".into(),
+            success: duplicates == 0 && failed == 0,
+            message,
         }))
     }
 
@@ -141,51 +418,71 @@ impl PinCodeVaultService for RustPinCodeVault {
         &self,
         request: Request<GenerationRequest>,
     ) -> Result<Response<StatusResponse>, Status> {
-        let count = request.into_inner().count;
-        println!("Generating {} PIN codes", count);
-
-        let cipher = match &self.cipher {
-            Some(c) => c,
-            None => {
-                println!("No cipher available!");
-                return Err(Status::internal("Cipher not initialized"));
-            }
-        };
+        let uploader = caller(&request);
+        let request = request.into_inner();
+        let count = request.count;
+        let product = required_product(request.product).map_err(Status::invalid_argument)?;
+        println!("Generating {} PIN codes of {:?}", count, product);
+
+        if self.cipher.is_none() {
+            println!("No cipher available!");
+            return Err(Status::internal("Cipher not initialized"));
+        }
+
+        let batch = self
+            .start_batch(BatchSource::Generated, product, None, None, uploader)
+            .await?;
 
         // Create a vector of futures
         let mut tasks = Vec::new();
         for _ in 0..count {
+            let batch = &batch;
             let job = async move {
-                let pin = utils::generate_random_pin(16);
-                let encrypted = cipher.enc_encrypt(pin.clone());
-
-                println!("{encrypted}");
                 let repo = self.pincode_repo.clone(); // Make sure your repo is Arc<dyn ...>
-                let pin_code = PinCode {
-                    pincode: pin.clone(),
-                    encrypted: encrypted.clone(),
-                    status: PinStatus::Active,
-                    created_at: Some(DateTime::now()),
-                    expires_at: None,
-                    id: None,
-                    purchased_at: None,
-                    reservation_id: None,
-                    reserved_at: None,
-                };
-                if let Err(e) = repo.insert_one(pin_code).await {
-                    println!("Insert failed: {:?}", e);
+                for _ in 0..MAX_GENERATE_ATTEMPTS {
+                    let pin = utils::generate_random_pin(16).into();
+                    let pin_code = new_pin_code(&repo, batch, pin).await?;
+
+                    println!("{}", pin_code.encrypted);
+                    match repo.insert_one(pin_code).await {
+                        Ok(_) => return Ok(true),
+                        // Already in the vault, so draw another PIN
+                        Err(e) if is_duplicate_key(&e) => continue,
+                        Err(e) => {
+                            println!("Insert failed: {:?}", e);
+                            return Ok(false);
+                        }
+                    }
                 }
+                println!("No unique PIN after {} attempts", MAX_GENERATE_ATTEMPTS);
+                Ok::<bool, DataKeyError>(false)
             };
 
             tasks.push(job);
         }
 
-        // Run all jobs in parallel
-        join_all(tasks).await;
+        // Run all jobs in parallel; the batch is completed even if some could not seal
+        let (mut stored, mut error) = (0, None);
+        for result in join_all(tasks).await {
+            match result {
+                Ok(true) => stored += 1,
+                Ok(false) => {}
+                Err(e) => {
+                    println!("Sealing failed: {}", e);
+                    error.get_or_insert(e);
+                }
+            }
+        }
+        self.complete_batch(&batch, stored, None, error.as_ref()).await?;
+        self.settle_recall(&batch).await?;
+        if let Some(e) = error {
+            return Err(e.into());
+        }
 
+        // Failed inserts and PINs that stayed duplicate are logged above and leave a shortfall
         Ok(Response::new(StatusResponse {
-            success: true,
-            message: format!("Generated {} PIN code(s)", count),
+            success: stored == i64::from(count),
+            message: format!("Generated {} of {} PIN code(s) (batch {})", stored, count, batch.id),
         }))
     }
 
@@ -199,8 +496,9 @@ impl PinCodeVaultService for RustPinCodeVault {
             Some(pin_code) => Ok(Response::new(PinCodeResponse {
                 success: true,
                 message: "PIN found".into(),
+                // The response copy is owned by prost and cannot be wiped
+                pin_code: self.decrypt_pin(&pin_code).await?.expose().clone(),
                 id,
-                pin_code: pin_code.pincode,
             })),
             None => Ok(Response::new(PinCodeResponse {
                 success: false,
@@ -213,79 +511,384 @@ impl PinCodeVaultService for RustPinCodeVault {
 
     async fn reserve_pin_code(
         &self,
-        _request: Request<()>,
+        request: Request<ReservationRequest>,
     ) -> Result<Response<ReservationResponse>, Status> {
-        match self.pincode_repo.find_available().await {
-            Some(pin_code) => {
-                let now = DateTime::now();
-                let expires_at = DateTime::from_chrono(now.to_chrono() + Duration::minutes(3));
-
-                // Insert reservation
-                let rev_id = self
-                    .reservation_repo
-                    .insert_one(PinCodeReservation {
-                        pincode_id: pin_code.id.clone(),
-                        reserved_at: now,
-                        id: None,
-                    })
-                    .await
-                    .map_err(|e| {
-                        Status::internal(format!("Failed to insert reservation: {}", e))
-                    })?;
-
-                // Update the pin code's statusself.pincode_repo
-                self.pincode_repo
-                    .reserve_pincode(
-                        &pin_code.id.as_ref().unwrap().to_hex(),
-                        &rev_id.to_hex(),
-                        now,
-                        expires_at,
-                    )
-                    .await
-                    .map_err(|e| Status::internal(format!("Failed to reserve pin code: {}", e)))?;
-
-                Ok(Response::new(ReservationResponse {
-                    success: true,
-                    message: "PIN reserved".into(),
-                    id: rev_id.to_hex(), // Convert ObjectId to hex string
-                }))
-            }
-            None => Ok(Response::new(ReservationResponse {
-                success: false,
-                message: "No PIN Available!".into(),
-                id: "".into(),
-            })),
+        let request = request.into_inner();
+        self.reserve(1, request.ttl_seconds, request.product).await
+    }
+
+    async fn reserve_pin_codes(
+        &self,
+        request: Request<BulkReservationRequest>,
+    ) -> Result<Response<ReservationResponse>, Status> {
+        let request = request.into_inner();
+        if !(1..=MAX_BULK_RESERVATION).contains(&request.count) {
+            return Err(Status::invalid_argument(format!(
+                "count must be between 1 and {}",
+                MAX_BULK_RESERVATION
+            )));
         }
+        self.reserve(request.count as usize, request.ttl_seconds, request.product).await
     }
 
+    type TakePinCodeStream = Pin<Box<dyn Stream<Item = Result<PinCodeResponse, Status>> + Send>>;
+
     async fn take_pin_code(
         &self,
         request: Request<IdRequest>,
-    ) -> Result<Response<PinCodeResponse>, Status> {
+    ) -> Result<Response<Self::TakePinCodeStream>, Status> {
+        let id = request.into_inner().id;
+        println!("Purchasing PINs for Reservation ID: {}", id);
+        let reservation_id = ObjectId::parse_str(&id)
+            .map_err(|_| Status::invalid_argument("Invalid reservation ID"))?;
+
+        let now = DateTime::now();
+        let pin_codes = self.reservations.find_reserved(reservation_id, now).await?;
+        // Decrypt first so a broken ciphertext is never marked as sold
+        let pins = join_all(pin_codes.iter().map(|pin_code| self.decrypt_pin(pin_code)))
+            .await
+            .into_iter()
+            .collect::<Result<Vec<_>, _>>()?;
+        self.reservations.purchase(&pin_codes, reservation_id, now).await?;
+
+        let responses: Vec<_> = pins
+            .iter()
+            .map(|pin| PinCodeResponse {
+                success: true,
+                message: "PIN reserved".into(),
+                id: id.clone(),
+                pin_code: pin.expose().clone(),
+            })
+            .collect();
+        Ok(Response::new(Box::pin(futures::stream::iter(responses).map(Ok))))
+    }
+
+    async fn release_reservation(
+        &self,
+        request: Request<IdRequest>,
+    ) -> Result<Response<StatusResponse>, Status> {
         let id = request.into_inner().id;
-        println!("Purchasing PIN for Reservation ID: {}", id);
-        match self.pincode_repo.find_by_reservation_id(&id).await {
-            Some(pin_code) => {
-                let now = DateTime::now();
-
-                self.pincode_repo
-                    .purchase_pincode(&pin_code.id.as_ref().unwrap().to_hex(), now)
-                    .await
-                    .map_err(|e| Status::internal(format!("Failed to reserve pin code: {}", e)))?;
-
-                Ok(Response::new(PinCodeResponse {
-                    success: true,
-                    message: "PIN reserved".into(),
-                    id: id.into(),
-                    pin_code: pin_code.pincode.into(),
-                }))
+        let reservation_id = ObjectId::parse_str(&id)
+            .map_err(|_| Status::invalid_argument("Invalid reservation ID"))?;
+
+        self.reservations.release(reservation_id, DateTime::now()).await?;
+        println!("Released reservation {}", id);
+
+        Ok(Response::new(StatusResponse {
+            success: true,
+            message: "Reservation released".into(),
+        }))
+    }
+
+    async fn extend_reservation(
+        &self,
+        request: Request<ExtensionRequest>,
+    ) -> Result<Response<ReservationResponse>, Status> {
+        let request = request.into_inner();
+        let reservation_id = ObjectId::parse_str(&request.id)
+            .map_err(|_| Status::invalid_argument("Invalid reservation ID"))?;
+
+        let now = DateTime::now();
+        let expires_at = self
+            .expires_at(now, request.ttl_seconds)
+            .ok_or_else(|| Status::invalid_argument("ttl_seconds must not be negative"))?;
+        let max_hold = Duration::seconds(self.reservation_conf.max_ttl_secs.into());
+        let expires_at = self
+            .reservations
+            .extend(reservation_id, now, expires_at, max_hold)
+            .await?;
+
+        Ok(Response::new(ReservationResponse {
+            success: true,
+            message: "Reservation extended".into(),
+            id: request.id,
+            expires_at: Some(expires_at.to_system_time().into()),
+        }))
+    }
+
+    async fn reencrypt_pin_codes(
+        &self,
+        request: Request<ReencryptionRequest>,
+    ) -> Result<Response<ReencryptionResponse>, Status> {
+        let request = request.into_inner();
+        if self.cipher.is_none() {
+            return Err(Status::internal("Cipher not initialized"));
+        }
+        let guard = self
+            .reencryption_lock
+            .clone()
+            .try_lock_owned()
+            .map_err(|_| Status::failed_precondition("Re-encryption is already running"))?;
+
+        let batch_size = if request.batch_size > 0 {
+            request.batch_size as i64
+        } else {
+            rotation::DEFAULT_BATCH_SIZE
+        };
+        println!("Re-encrypting PIN codes in batches of {}", batch_size);
+
+        // Run detached so a dropped client connection does not abort the job halfway;
+        // progress is checkpointed and read back through GetReencryptionStatus
+        let pincode_repo = self.pincode_repo.clone();
+        let checkpoint_repo = self.checkpoint_repo.clone();
+        tokio::spawn(async move {
+            let _guard = guard;
+            match rotation::reencrypt_pin_codes(pincode_repo, checkpoint_repo, batch_size, request.restart)
+                .await
+            {
+                Ok(checkpoint) => println!(
+                    "Re-encryption complete: migrated {}, skipped {}, failed {}",
+                    checkpoint.migrated, checkpoint.skipped, checkpoint.failed
+                ),
+                Err(e) => println!("Re-encryption interrupted: {}", e),
             }
-            None => Ok(Response::new(PinCodeResponse {
-                success: false,
-                message: "Reservation does not exist anymore!".into(),
-                id: "".into(),
-                pin_code: "".into(),
-            })),
+        });
+
+        Ok(Response::new(ReencryptionResponse {
+            success: true,
+            message: "Re-encryption started".into(),
+            migrated: 0,
+            skipped: 0,
+            failed: 0,
+        }))
+    }
+
+    async fn get_reencryption_status(
+        &self,
+        _request: Request<()>,
+    ) -> Result<Response<ReencryptionStatusResponse>, Status> {
+        let running = self.reencryption_lock.try_lock().is_err();
+        let checkpoint = self
+            .checkpoint_repo
+            .find_by_id(rotation::REENCRYPTION_JOB_ID)
+            .await
+            .map_err(|e| Status::internal(format!("Failed to load re-encryption checkpoint: {}", e)))?;
+
+        let Some(checkpoint) = checkpoint else {
+            return Ok(Response::new(ReencryptionStatusResponse {
+                running,
+                message: if running { "Re-encryption starting" } else { "Re-encryption never ran" }.into(),
+                ..Default::default()
+            }));
+        };
+        let message = match (running, checkpoint.completed) {
+            (true, _) => "Re-encryption running",
+            (false, true) => "Re-encryption complete",
+            // The job stopped on an error; starting it again resumes from the checkpoint
+            (false, false) => "Re-encryption interrupted",
+        };
+
+        Ok(Response::new(ReencryptionStatusResponse {
+            running,
+            completed: checkpoint.completed,
+            message: message.into(),
+            migrated: checkpoint.migrated,
+            skipped: checkpoint.skipped,
+            failed: checkpoint.failed,
+            unbound: checkpoint.unbound,
+            last_id: checkpoint.last_id.map(|id| id.to_hex()).unwrap_or_default(),
+            started_at: Some(timestamp(checkpoint.started_at)),
+            updated_at: Some(timestamp(checkpoint.updated_at)),
+        }))
+    }
+
+    async fn strip_plaintext_pin_codes(
+        &self,
+        request: Request<MigrationRequest>,
+    ) -> Result<Response<MigrationResponse>, Status> {
+        let request = request.into_inner();
+        if self.cipher.is_none() {
+            return Err(Status::internal("Cipher not initialized"));
         }
+
+        let batch_size = if request.batch_size > 0 {
+            request.batch_size as i64
+        } else {
+            migration::DEFAULT_BATCH_SIZE
+        };
+        println!("Stripping plaintext PIN codes in batches of {}", batch_size);
+
+        let report = migration::strip_plaintext_pins(self.pincode_repo.clone(), batch_size)
+            .await
+            .map_err(|e| Status::internal(format!("Migration interrupted: {}", e)))?;
+
+        Ok(Response::new(MigrationResponse {
+            success: report.failed == 0,
+            message: "Plaintext migration complete".into(),
+            stripped: report.stripped,
+            failed: report.failed,
+        }))
+    }
+
+    async fn rewrap_data_keys(
+        &self,
+        _request: Request<()>,
+    ) -> Result<Response<ReencryptionResponse>, Status> {
+        println!("Rewrapping data keys under the active master key");
+        let report = self.pincode_repo.keys().rewrap_all().await?;
+
+        Ok(Response::new(ReencryptionResponse {
+            success: report.failed == 0,
+            message: "Data keys rewrapped".into(),
+            migrated: report.rewrapped,
+            skipped: report.skipped,
+            failed: report.failed,
+        }))
+    }
+
+    async fn list_batches(
+        &self,
+        request: Request<BatchListRequest>,
+    ) -> Result<Response<BatchListResponse>, Status> {
+        let request = request.into_inner();
+        let limit = match request.limit {
+            0 => DEFAULT_BATCH_PAGE,
+            limit => limit.clamp(1, MAX_BATCH_PAGE),
+        };
+        let before = match non_empty(request.before_id) {
+            Some(id) => Some(
+                ObjectId::parse_str(&id).map_err(|_| Status::invalid_argument("Invalid batch ID"))?,
+            ),
+            None => None,
+        };
+
+        let batches = self
+            .batch_repo
+            .find_page(before, limit.into())
+            .await
+            .map_err(|e| Status::internal(format!("Failed to list batches: {}", e)))?;
+
+        Ok(Response::new(BatchListResponse {
+            batches: batches.iter().map(batch_response).collect(),
+        }))
+    }
+
+    async fn get_batch_status(
+        &self,
+        request: Request<IdRequest>,
+    ) -> Result<Response<BatchStatusResponse>, Status> {
+        let id = ObjectId::parse_str(request.into_inner().id)
+            .map_err(|_| Status::invalid_argument("Invalid batch ID"))?;
+
+        let batch = self
+            .batch_repo
+            .find_by_id(id)
+            .await
+            .map_err(|e| Status::internal(format!("Failed to load batch: {}", e)))?
+            .ok_or_else(|| Status::not_found("Batch does not exist"))?;
+        let status_counts = self
+            .pincode_repo
+            .count_by_status(id)
+            .await
+            .map_err(|e| Status::internal(format!("Failed to count batch PINs: {}", e)))?;
+
+        Ok(Response::new(BatchStatusResponse {
+            batch: Some(batch_response(&batch)),
+            status_counts: status_counts.into_iter().collect(),
+        }))
+    }
+
+    async fn recall_batch(
+        &self,
+        request: Request<RecallRequest>,
+    ) -> Result<Response<RecallResponse>, Status> {
+        let request = request.into_inner();
+        let batch_id = ObjectId::parse_str(&request.batch_id)
+            .map_err(|_| Status::invalid_argument("Invalid batch ID"))?;
+        self.batch_repo
+            .find_by_id(batch_id)
+            .await
+            .map_err(|e| Status::internal(format!("Failed to load batch: {}", e)))?
+            .ok_or_else(|| Status::not_found("Batch does not exist"))?;
+
+        // Marked first, so an upload still storing into the batch voids what it stores.
+        // A repeat keeps the original record and only voids what an interrupted
+        // recall left behind.
+        let now = DateTime::now();
+        let first = self
+            .batch_repo
+            .mark_recalled(batch_id, non_empty(request.reason), now)
+            .await
+            .map_err(|e| Status::internal(format!("Failed to record recall of batch {}: {}", batch_id, e)))?;
+        let recall = self
+            .reservations
+            .recall(batch_id, now)
+            .await
+            .map_err(|e| Status::internal(format!("Failed to recall batch: {}", e)))?;
+
+        let purchased = self
+            .pincode_repo
+            .count_by_status(batch_id)
+            .await
+            .map_err(|e| Status::internal(format!("Failed to count batch PINs: {}", e)))?
+            .into_iter()
+            .find(|(status, _)| *status == PinStatus::Purchased.to_string())
+            .map_or(0, |(_, count)| count);
+        println!(
+            "Recalled batch {}: {} active and {} reserved PINs voided, {} reservations affected, {} PINs released",
+            batch_id,
+            recall.active,
+            recall.reserved,
+            recall.reservations.len(),
+            recall.released
+        );
+
+        let outcome = if first { "Batch recalled" } else { "Batch was already recalled" };
+        Ok(Response::new(RecallResponse {
+            success: true,
+            message: format!(
+                "{}: {} PIN code(s) voided, {} already sold",
+                outcome,
+                recall.active + recall.reserved,
+                purchased
+            ),
+            voided_active: recall.active as i64,
+            voided_reserved: recall.reserved as i64,
+            reservation_ids: recall.reservations.into_iter().map(ObjectId::to_hex).collect(),
+            purchased,
+            released: recall.released as i64,
+        }))
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn product(operator: &str, denomination: i64, currency: &str) -> vault::Product {
+        vault::Product {
+            operator: operator.into(),
+            denomination,
+            currency: currency.into(),
+        }
+    }
+
+    #[test]
+    fn to_product_trims_and_normalizes() {
+        let product = to_product(product("  Safaricom ", 5000, " kes")).unwrap();
+        assert_eq!(
+            product,
+            Product {
+                operator: "Safaricom".into(),
+                denomination: 5000,
+                currency: "KES".into(),
+            }
+        );
+    }
+
+    #[test]
+    fn to_product_rejects_invalid_fields() {
+        assert!(to_product(product(" ", 5000, "KES")).is_err());
+        assert!(to_product(product("Safaricom", 0, "KES")).is_err());
+        assert!(to_product(product("Safaricom", -100, "KES")).is_err());
+        for currency in ["", "KE", "KESH", "K3S"] {
+            assert!(to_product(product("Safaricom", 5000, currency)).is_err(), "{currency:?}");
+        }
+    }
+
+    #[test]
+    fn required_product_rejects_missing_product() {
+        assert!(required_product(None).is_err());
+        assert!(required_product(Some(product("Safaricom", 5000, "KES"))).is_ok());
     }
 }
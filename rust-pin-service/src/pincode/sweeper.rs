@@ -0,0 +1,29 @@
+use std::time::Duration;
+
+use bson::DateTime;
+use tokio::task::JoinHandle;
+
+use crate::application::AppContext;
+use crate::pincode::reservation::ReservationStore;
+
+const SWEEP_BATCH_SIZE: i64 = 500;
+
+/// Periodically returns PINs whose hold lapsed to the pool, so they do not sit
+/// `Reserved` until the next reservation happens to reclaim them.
+pub fn start(context: &AppContext) -> JoinHandle<()> {
+    let store = ReservationStore::new(context);
+    let period = Duration::from_secs(context.env.reservation.sweep_interval_secs.into());
+
+    tokio::spawn(async move {
+        let mut interval = tokio::time::interval(period);
+        interval.set_missed_tick_behavior(tokio::time::MissedTickBehavior::Delay);
+        loop {
+            interval.tick().await;
+            match store.expire_lapsed(DateTime::now(), SWEEP_BATCH_SIZE).await {
+                Ok(0) => {}
+                Ok(reclaimed) => println!("Reservation sweep reclaimed {} expired PINs", reclaimed),
+                Err(e) => println!("Reservation sweep failed: {}", e),
+            }
+        }
+    })
+}
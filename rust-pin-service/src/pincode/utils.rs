@@ -4,5 +4,5 @@ use rand::distributions::Uniform;
 pub fn generate_random_pin(length: usize) -> String {
     let mut rng = thread_rng();
     let digits = Uniform::from(0..10);
-    (0..length).map(|_| rng.sample(&digits).to_string()).collect()
+    (0..length).map(|_| rng.sample(digits).to_string()).collect()
 } 
\ No newline at end of file